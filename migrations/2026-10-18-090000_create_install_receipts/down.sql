@@ -0,0 +1,3 @@
+DROP TABLE runtime_dependencies;
+
+DROP TABLE install_receipts;
@@ -0,0 +1,19 @@
+CREATE TABLE install_receipts (
+  bottle_id INTEGER NOT NULL PRIMARY KEY REFERENCES downloaded_bottles ON DELETE CASCADE,
+  homebrew_version TEXT,
+  tap TEXT,
+  compiler TEXT,
+  built_as_bottle BOOLEAN NOT NULL,
+  installed_on_request BOOLEAN NOT NULL
+);
+
+CREATE TABLE runtime_dependencies (
+  id INTEGER NOT NULL PRIMARY KEY,
+  bottle_id INTEGER NOT NULL REFERENCES downloaded_bottles ON DELETE CASCADE,
+  full_name TEXT NOT NULL,
+  version TEXT NOT NULL,
+  revision INTEGER NOT NULL,
+  -- NULL for older receipts, which list every dependency recursively
+  declared_directly BOOLEAN,
+  UNIQUE (bottle_id, full_name)
+);
@@ -0,0 +1 @@
+DROP TABLE bottle_files;
@@ -0,0 +1,14 @@
+CREATE TABLE bottle_files (
+  id INTEGER NOT NULL PRIMARY KEY,
+  bottle_id INTEGER NOT NULL REFERENCES downloaded_bottles ON DELETE CASCADE,
+  -- Relative to the bottle's path
+  path TEXT NOT NULL,
+  -- One of 'file', 'directory', 'symlink' or 'hard_link'
+  kind TEXT NOT NULL,
+  mode INTEGER NOT NULL,
+  -- For symlinks, the size and hash are of the link target
+  size BIGINT NOT NULL,
+  sha256 TEXT,
+  relocated BOOLEAN NOT NULL,
+  UNIQUE (bottle_id, path)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE link_preferences (
+  -- The name of the link, e.g. 'python3'
+  name TEXT NOT NULL PRIMARY KEY,
+  -- The formula whose file should be linked when several provide it
+  formula TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+DROP TABLE forced_links;
+
+ALTER TABLE downloaded_bottles DROP COLUMN keg_only_reason;
@@ -0,0 +1,7 @@
+-- NULL unless the bottle is keg-only, i.e. should not be linked by default
+ALTER TABLE downloaded_bottles ADD COLUMN keg_only_reason TEXT;
+
+-- Keg-only formulae that the user asked to be linked anyway
+CREATE TABLE forced_links (
+  name TEXT NOT NULL PRIMARY KEY
+);
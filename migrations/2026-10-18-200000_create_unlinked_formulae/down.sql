@@ -0,0 +1 @@
+DROP TABLE unlinked_formulae;
@@ -0,0 +1,4 @@
+-- Formulae that the user unlinked with `chug unlink`
+CREATE TABLE unlinked_formulae (
+  name TEXT NOT NULL PRIMARY KEY
+);
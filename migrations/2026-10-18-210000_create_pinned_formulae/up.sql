@@ -0,0 +1,4 @@
+-- Formulae that `chug update` should keep at their downloaded version
+CREATE TABLE pinned_formulae (
+  name TEXT NOT NULL PRIMARY KEY
+);
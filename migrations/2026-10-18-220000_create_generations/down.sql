@@ -0,0 +1,5 @@
+DROP TABLE generation_links;
+
+DROP TABLE generation_dependencies;
+
+DROP TABLE generations;
@@ -0,0 +1,25 @@
+-- A snapshot of the installed bottles and their links, for `chug rollback`
+CREATE TABLE generations (
+  id INTEGER NOT NULL PRIMARY KEY,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- A copy of the dependencies table for each generation
+CREATE TABLE generation_dependencies (
+  id INTEGER NOT NULL PRIMARY KEY,
+  generation_id INTEGER NOT NULL REFERENCES generations ON DELETE CASCADE,
+  -- Set to NULL to indicate that the bottle was manually installed
+  dependent_id INTEGER REFERENCES downloaded_bottles ON DELETE CASCADE,
+  dependency_id INTEGER NOT NULL REFERENCES downloaded_bottles ON DELETE CASCADE,
+  UNIQUE (generation_id, dependent_id, dependency_id)
+);
+
+-- A copy of the linked_files table for each generation
+CREATE TABLE generation_links (
+  id INTEGER NOT NULL PRIMARY KEY,
+  generation_id INTEGER NOT NULL REFERENCES generations ON DELETE CASCADE,
+  bottle_id INTEGER NOT NULL REFERENCES downloaded_bottles ON DELETE CASCADE,
+  path TEXT NOT NULL,
+  target TEXT NOT NULL,
+  UNIQUE (generation_id, path)
+);
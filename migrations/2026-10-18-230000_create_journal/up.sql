@@ -0,0 +1,10 @@
+-- The action that chug is part way through, so that it can be recovered if
+-- chug is interrupted. There is at most one row.
+CREATE TABLE journal (
+  id INTEGER NOT NULL PRIMARY KEY CHECK (id = 1),
+  -- The generation to restore if the action is undone
+  generation_id INTEGER NOT NULL REFERENCES generations,
+  -- Set once the new dependencies are saved, after which the action is
+  -- finished rather than undone
+  committed BOOLEAN NOT NULL DEFAULT FALSE
+);
@@ -1,20 +1,33 @@
 use std::{
-    collections::{BTreeMap, BTreeSet},
-    fmt,
+    collections::{BTreeMap, BTreeSet, btree_map::Entry},
+    fmt, fs,
 };
 
+use anyhow::Context;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 use crate::{
-    db::models::{Dependency, DownloadedBottle},
+    db::models::{
+        Dependency, DownloadedBottle, ForcedLink, Journal, PinnedFormula, Receipt,
+        RuntimeDependency,
+    },
     formulae::Formula,
-    status::{Progress, print_list},
+    generations, journal,
+    links::LinkPlan,
+    plan::{Plan, PlannedBottle},
+    receipt,
+    status::Progress,
 };
 
 #[derive(Debug)]
 pub struct BottleForestSnapshot {
     bottles: BTreeMap<i32, DownloadedBottle>,
     dependencies: Vec<Dependency>,
+    /// From the install receipts, keyed by bottle id. Bottles without a
+    /// receipt have no entry.
+    runtime_dependencies: BTreeMap<i32, Vec<RuntimeDependency>>,
+    forced_links: Vec<ForcedLink>,
+    pinned: Vec<PinnedFormula>,
 }
 
 #[derive(Debug)]
@@ -22,6 +35,15 @@ pub struct ActionBuilder<'a> {
     snapshot: &'a BottleForestSnapshot,
     bottles: BTreeSet<BottleRef<'a>>,
     dependencies: BTreeSet<(Option<BottleRef<'a>>, BottleRef<'a>)>,
+    overwrite: bool,
+    force_link: bool,
+    /// Names of keg-only formulae to link anyway.
+    forced_links: BTreeSet<&'a str>,
+    pinned: BTreeSet<&'a str>,
+    /// Bottles that keep their current version due to a pin, along with the
+    /// name of the pinned formula that keeps them, i.e. the pinned bottles and
+    /// everything they depend on.
+    held_by_pin: BTreeMap<BottleRef<'a>, &'a str>,
 }
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
@@ -32,56 +54,113 @@ struct BottleRef<'a> {
 
 impl BottleForestSnapshot {
     pub fn new() -> anyhow::Result<Self> {
-        let bottles = DownloadedBottle::get_all()?
+        let bottles = DownloadedBottle::get_installed()?
             .into_iter()
             .map(|b| (b.id(), b))
             .collect();
         let dependencies = Dependency::get_all()?;
+        let mut runtime_dependencies = Receipt::bottle_ids()?
+            .into_iter()
+            .map(|id| (id, Vec::new()))
+            .collect::<BTreeMap<_, _>>();
+        for dependency in RuntimeDependency::get_all()? {
+            runtime_dependencies
+                .entry(dependency.bottle_id())
+                .or_default()
+                .push(dependency);
+        }
+
+        let forced_links = ForcedLink::get_all()?;
+        let pinned = PinnedFormula::get_all()?;
 
         Ok(Self {
             bottles,
             dependencies,
+            runtime_dependencies,
+            forced_links,
+            pinned,
         })
     }
+
+    fn contains(&self, bottle_ref: BottleRef) -> bool {
+        self.bottles
+            .values()
+            .any(|b| BottleRef::from(b) == bottle_ref)
+    }
+
+    fn runtime_dependencies(&self, bottle_ref: BottleRef) -> Option<&[RuntimeDependency]> {
+        let (id, _) = self
+            .bottles
+            .iter()
+            .find(|&(_, b)| BottleRef::from(b) == bottle_ref)?;
+        self.runtime_dependencies.get(id).map(Vec::as_slice)
+    }
 }
 
 impl<'a> ActionBuilder<'a> {
-    pub fn new(snapshot: &'a BottleForestSnapshot) -> Self {
+    pub fn new(snapshot: &'a BottleForestSnapshot) -> anyhow::Result<Self> {
+        let get_bottle = |id: i32| {
+            snapshot.bottles.get(&id).map(BottleRef::from).context(
+                "A dependency refers to a missing bottle. Run `chug doctor --fix` to repair the DB",
+            )
+        };
+
         let bottles = snapshot.bottles.values().map(BottleRef::from).collect();
         let dependencies = snapshot
             .dependencies
             .iter()
             .map(|dep| {
-                (
-                    dep.dependent_id()
-                        .map(|id| snapshot.bottles.get(&id).unwrap().into()),
-                    snapshot.bottles.get(&dep.dependency_id()).unwrap().into(),
-                )
+                Ok((
+                    dep.dependent_id().map(get_bottle).transpose()?,
+                    get_bottle(dep.dependency_id())?,
+                ))
             })
-            .collect();
+            .collect::<anyhow::Result<_>>()?;
 
-        Self {
+        let mut builder = Self {
             snapshot,
             bottles,
             dependencies,
-        }
+            overwrite: false,
+            force_link: false,
+            forced_links: snapshot.forced_links.iter().map(|l| l.name()).collect(),
+            pinned: snapshot.pinned.iter().map(|p| p.name()).collect(),
+            held_by_pin: BTreeMap::new(),
+        };
+        builder.held_by_pin = builder.pinned_closure();
+
+        Ok(builder)
+    }
+
+    /// Replace files in the bin dir that were not created by chug.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+
+        self
+    }
+
+    /// Link keg-only bottles that are added by `add_bottles`.
+    pub fn force_link(mut self, force_link: bool) -> Self {
+        self.force_link = force_link;
+
+        self
     }
 
     pub fn add_bottles(mut self, bottles: &[String]) -> anyhow::Result<Self> {
         for name in bottles {
-            if self
-                .bottles
-                .range(BottleRef { name, version: "" }..)
-                .take_while(|b| b.name == name)
-                .count()
-                > 0
-            {
+            if let Some(bottle) = self.bottles.iter().find(|b| b.name == name) {
+                if self.force_link {
+                    self.forced_links.insert(bottle.name);
+                }
                 continue;
             }
 
             let formula = Formula::get(name)?;
             self.bottles.insert(formula.into());
             self.dependencies.insert((None, formula.into()));
+            if self.force_link {
+                self.forced_links.insert(&formula.name);
+            }
         }
 
         Ok(self)
@@ -122,51 +201,184 @@ impl<'a> ActionBuilder<'a> {
     }
 
     pub fn update(mut self) -> anyhow::Result<Self> {
+        // Pinned bottles keep their current version, along with everything
+        // they depend on
+        let kept = self.held_by_pin.keys().copied().collect::<BTreeSet<_>>();
+        let kept_dependencies = self
+            .dependencies
+            .iter()
+            .copied()
+            .filter(|(a, _)| a.is_some_and(|a| kept.contains(&a)))
+            .collect::<Vec<_>>();
+
         let roots = self
             .dependencies
             .iter()
             .filter(|(a, _)| a.is_none())
-            .map(|(_, b)| Formula::get_exact(b.name))
-            .collect::<Result<Vec<_>, _>>()?;
+            .map(|&(_, b)| {
+                if kept.contains(&b) {
+                    Ok(b)
+                } else {
+                    Formula::get_exact(b.name).map(BottleRef::from)
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
-        self.bottles = roots.iter().copied().map(BottleRef::from).collect();
+        self.bottles = kept.into_iter().chain(roots.iter().copied()).collect();
         self.dependencies = roots
-            .iter()
-            .copied()
-            .map(|f| (None, BottleRef::from(f)))
+            .into_iter()
+            .map(|b| (None, b))
+            .chain(kept_dependencies)
             .collect();
 
         Ok(self)
     }
 
-    pub fn run(mut self) -> anyhow::Result<()> {
+    /// Works out what `run` will change, without changing anything. The only
+    /// requests made are for the sizes of bottles that need downloading.
+    pub fn plan(&mut self) -> anyhow::Result<Plan> {
         self.fix_dependencies()?;
+        self.forced_links
+            .retain(|&name| self.bottles.iter().any(|b| b.name == name));
+
+        let (to_add, to_remove) = self.diff();
+
+        // Bottles may already be downloaded, e.g. if they are kept for
+        // `chug rollback`
+        let mut downloaded = Vec::new();
+        let mut to_download = Vec::new();
+        for &bottle_ref in &to_add {
+            match DownloadedBottle::get(bottle_ref.name, bottle_ref.version)? {
+                Some(bottle) => downloaded.push(bottle),
+                None => to_download.push(bottle_ref),
+            }
+        }
+        // The sizes are only informational, so don't fail the action over them
+        let download_sizes = to_download
+            .par_iter()
+            .map(|&bottle_ref| {
+                let size = Formula::get_exact(bottle_ref.name)
+                    .and_then(|f| f.bottle.stable.current_target()?.download_size());
+                let size = match size {
+                    Ok(size) => Some(size),
+                    Err(err) => {
+                        eprintln!(
+                            "Warning: could not get the download size of {bottle_ref}: {err}"
+                        );
+                        None
+                    }
+                };
+
+                (bottle_ref, size)
+            })
+            .collect::<BTreeMap<_, _>>();
 
-        let (to_add, to_remove) = diff_bottles(
-            &self
+        let added = to_add
+            .iter()
+            .map(|&bottle_ref| PlannedBottle {
+                name: bottle_ref.name.to_owned(),
+                version: bottle_ref.version.to_owned(),
+                download: download_sizes.contains_key(&bottle_ref),
+                download_size: download_sizes.get(&bottle_ref).copied().flatten(),
+            })
+            .collect();
+        let removed = to_remove
+            .iter()
+            .map(|bottle_ref| PlannedBottle {
+                name: bottle_ref.name.to_owned(),
+                version: bottle_ref.version.to_owned(),
+                download: false,
+                download_size: None,
+            })
+            .collect();
+        let mut plan = Plan::from_bottles(added, removed);
+
+        // Only the files in bottles that are already downloaded are known
+        let bottles = self
+            .bottles
+            .iter()
+            .filter_map(|&bottle_ref| {
+                self.snapshot
+                    .bottles
+                    .values()
+                    .chain(&downloaded)
+                    .find(|b| BottleRef::from(*b) == bottle_ref)
+            })
+            .collect::<Vec<_>>();
+        let link_plan = LinkPlan::with_forced_links(
+            bottles.iter().copied(),
+            self.overwrite,
+            &self.forced_links,
+        )?;
+        let mut links = BTreeMap::new();
+        for bottle in &bottles {
+            for (source, dest) in link_plan.links_for(bottle) {
+                links.insert(dest.to_owned(), source.to_owned());
+            }
+        }
+
+        let to_download_names = to_download.iter().map(|b| b.name).collect::<BTreeSet<_>>();
+        for bottle in self.snapshot.bottles.values() {
+            for linked_file in bottle.linked_files()? {
+                let path = linked_file.path();
+                match links.remove(path) {
+                    Some(source) => {
+                        if fs::read_link(path).ok() != Some(source) {
+                            plan.link.push(path.to_owned());
+                        }
+                    }
+                    // The new version may link it again once it is downloaded
+                    None if to_download_names.contains(bottle.name()) => {}
+                    None => plan.unlink.push(path.to_owned()),
+                }
+            }
+        }
+        plan.link.extend(links.into_keys());
+        plan.link.sort();
+        plan.unlink.sort();
+
+        Ok(plan)
+    }
+
+    pub fn run(mut self) -> anyhow::Result<()> {
+        let plan = self.plan()?;
+
+        let (to_add, to_remove) = self.diff();
+        let forced_links_changed = self.forced_links
+            != self
                 .snapshot
-                .bottles
-                .values()
-                .map(BottleRef::from)
-                .collect(),
-            &self.bottles,
-        );
+                .forced_links
+                .iter()
+                .map(|l| l.name())
+                .collect();
 
         anyhow::ensure!(
-            !to_add.is_empty() || !to_remove.is_empty(),
+            !to_add.is_empty() || !to_remove.is_empty() || forced_links_changed,
             "No bottles to add or remove",
         );
-        if !to_add.is_empty() {
-            println!("Adding bottles:");
-            print_list(&to_add)?;
-            println!();
-        }
-        if !to_remove.is_empty() {
-            println!("Removing bottles:");
-            print_list(&to_remove)?;
-            println!();
+        plan.print_bottles()?;
+
+        // Record what to go back to if anything goes wrong. Until the journal
+        // is committed, the action is undone rather than finished.
+        let mut journal = Journal::begin(&generations::checkpoint()?)?;
+        if let Err(err) = self.apply(&to_add, forced_links_changed) {
+            println!("Undoing changes...");
+            journal::undo(&journal)?;
+            return Err(err);
         }
+        journal.commit()?;
 
+        // Unlink old bottles, which are kept for `chug rollback` until the
+        // generations that use them are pruned
+        journal::finish(&journal)
+    }
+
+    /// Downloads and links the new bottles, then saves the new dependencies.
+    fn apply(
+        &self,
+        to_add: &BTreeSet<BottleRef<'a>>,
+        forced_links_changed: bool,
+    ) -> anyhow::Result<()> {
         // Add new bottles
         let progress = Progress::new();
         let downloaded_bottles = to_add
@@ -192,16 +404,6 @@ impl<'a> ActionBuilder<'a> {
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
 
-        downloaded_bottles
-            .par_iter()
-            .map(|bottle| {
-                bottle.link()?;
-
-                Ok(())
-            })
-            .collect::<anyhow::Result<Vec<()>>>()?;
-
-        // Save new dependencies to the DB
         let bottles_by_ref = self
             .snapshot
             .bottles
@@ -210,27 +412,52 @@ impl<'a> ActionBuilder<'a> {
             .map(|b| (BottleRef::from(b), b))
             .collect::<BTreeMap<_, _>>();
 
-        Dependency::replace_all(
-            self.dependencies
-                .iter()
-                .map(|(a, b)| (a.map(|a| bottles_by_ref[&a]), bottles_by_ref[b])),
+        if forced_links_changed {
+            ForcedLink::replace_all(self.forced_links.iter().copied())?;
+        }
+
+        let link_plan = LinkPlan::new(
+            self.bottles.iter().map(|b| bottles_by_ref[b]),
+            self.overwrite,
         )?;
+        link_plan.print_conflicts();
 
-        // Remove old bottles
-        to_remove
+        downloaded_bottles
             .par_iter()
-            .map(|bottle_ref| {
-                let bottle = bottles_by_ref[bottle_ref];
-                bottle.unlink()?;
-                bottle.remove()?;
+            .map(|bottle| {
+                bottle.link(&link_plan)?;
 
                 Ok(())
             })
             .collect::<anyhow::Result<Vec<()>>>()?;
+        // Existing bottles may have gained or lost files in a conflict
+        for bottle_ref in self.bottles.difference(to_add) {
+            bottles_by_ref[bottle_ref].link_files(&link_plan)?;
+        }
+
+        // Save new dependencies to the DB
+        Dependency::replace_all(
+            self.dependencies
+                .iter()
+                .map(|(a, b)| (a.map(|a| bottles_by_ref[&a]), bottles_by_ref[b])),
+        )?;
 
         Ok(())
     }
 
+    /// Returns the bottles to add and to remove.
+    fn diff(&self) -> (BTreeSet<BottleRef<'a>>, BTreeSet<BottleRef<'a>>) {
+        diff_bottles(
+            &self
+                .snapshot
+                .bottles
+                .values()
+                .map(BottleRef::from)
+                .collect(),
+            &self.bottles,
+        )
+    }
+
     // HACK: Technically `name` does not need to live for 'a, but I can't figure out how to express that
     fn get_bottle(&self, name: &'a str) -> Option<BottleRef<'a>> {
         self.bottles
@@ -240,6 +467,30 @@ impl<'a> ActionBuilder<'a> {
             .next()
     }
 
+    /// Maps each pinned bottle, and everything it depends on, to the pinned
+    /// formula that keeps it. Pinned bottles are kept by their own pin, even if
+    /// another pinned bottle depends on them.
+    fn pinned_closure(&self) -> BTreeMap<BottleRef<'a>, &'a str> {
+        let mut held = self
+            .bottles
+            .iter()
+            .filter(|b| self.pinned.contains(b.name))
+            .map(|&b| (b, b.name))
+            .collect::<BTreeMap<_, _>>();
+        let mut stack = held.keys().copied().collect::<Vec<_>>();
+        while let Some(bottle_ref) = stack.pop() {
+            let pin = held[&bottle_ref];
+            for dependency in self.get_dependencies(bottle_ref) {
+                if let Entry::Vacant(entry) = held.entry(dependency) {
+                    entry.insert(pin);
+                    stack.push(dependency);
+                }
+            }
+        }
+
+        held
+    }
+
     fn get_dependencies(&self, bottle_ref: BottleRef<'a>) -> impl Iterator<Item = BottleRef<'a>> {
         self.dependencies
             .range(
@@ -262,21 +513,15 @@ impl<'a> ActionBuilder<'a> {
     }
 
     fn add_dependencies(&mut self) -> Result<(), anyhow::Error> {
-        let mut stack = Vec::new();
-        for bottle in self.bottles.iter() {
-            let Ok(formula) = Formula::get_exact(bottle.name) else {
+        let mut stack = self.bottles.iter().copied().collect::<Vec<_>>();
+        while let Some(bottle_ref) = stack.pop() {
+            let Some(dependency_names) = self.dependency_names(bottle_ref) else {
                 continue;
             };
-            if formula.versions.stable != bottle.version {
-                continue;
-            }
-            stack.push(formula);
-        }
 
-        while let Some(formula) = stack.pop() {
-            let bottle_ref = BottleRef::from(formula);
-            for dependency_name in &formula.dependencies {
+            for dependency_name in dependency_names {
                 if let Some(dependency_ref) = self.get_bottle(dependency_name) {
+                    self.check_pinned_dependency(bottle_ref, dependency_ref)?;
                     self.dependencies.insert((Some(bottle_ref), dependency_ref));
                     continue;
                 }
@@ -285,13 +530,68 @@ impl<'a> ActionBuilder<'a> {
                 let dependency_ref = BottleRef::from(dependency);
                 self.bottles.insert(dependency_ref);
                 self.dependencies.insert((Some(bottle_ref), dependency_ref));
-                stack.push(dependency);
+                stack.push(dependency_ref);
             }
         }
 
         Ok(())
     }
 
+    /// Fails if a newly added bottle depends on a bottle that a pin keeps at
+    /// an older version than the one it was built against, either because the
+    /// dependency is pinned or because a pinned bottle depends on it.
+    fn check_pinned_dependency(
+        &self,
+        bottle_ref: BottleRef<'a>,
+        dependency_ref: BottleRef<'a>,
+    ) -> anyhow::Result<()> {
+        let Some(&pin) = self.held_by_pin.get(&dependency_ref) else {
+            return Ok(());
+        };
+        if self.snapshot.contains(bottle_ref) {
+            return Ok(());
+        }
+
+        let latest = &Formula::get_exact(dependency_ref.name)?.versions.stable;
+        if *latest == dependency_ref.version {
+            return Ok(());
+        }
+        let latest_ref = BottleRef {
+            name: dependency_ref.name,
+            version: latest,
+        };
+        if pin == dependency_ref.name {
+            anyhow::bail!(
+                "{bottle_ref} depends on {latest_ref}, but {pin} is pinned at {version}. \
+                Run `chug unpin {pin}` to allow it to be updated",
+                version = dependency_ref.version,
+            );
+        }
+        anyhow::bail!(
+            "{bottle_ref} depends on {latest_ref}, but {dependency_ref} is kept by the pin on \
+            {pin}, which depends on it. Run `chug unpin {pin}` to allow it to be updated",
+        );
+    }
+
+    /// Prefers the dependencies a downloaded bottle was built against over
+    /// those of the current formula.
+    fn dependency_names(&self, bottle_ref: BottleRef<'a>) -> Option<Vec<&'a str>> {
+        if let Some(runtime_dependencies) = self.snapshot.runtime_dependencies(bottle_ref) {
+            return Some(receipt::direct_dependencies(
+                bottle_ref.name,
+                runtime_dependencies
+                    .iter()
+                    .map(|d| (d.full_name(), d.declared_directly())),
+            ));
+        }
+
+        let formula = Formula::get_exact(bottle_ref.name).ok()?;
+        if formula.versions.stable != bottle_ref.version {
+            return None;
+        }
+        Some(formula.dependencies.iter().map(String::as_str).collect())
+    }
+
     fn remove_orphans(&mut self) {
         let mut ref_counts = self
             .bottles
@@ -300,10 +600,10 @@ impl<'a> ActionBuilder<'a> {
             .collect::<BTreeMap<_, _>>();
 
         self.dependencies.retain(|&(a, b)| {
-            if let Some(bottle) = a {
-                if !self.bottles.contains(&bottle) {
-                    return false;
-                }
+            if let Some(bottle) = a
+                && !self.bottles.contains(&bottle)
+            {
+                return false;
             }
             if !self.bottles.contains(&b) {
                 return false;
@@ -315,6 +615,14 @@ impl<'a> ActionBuilder<'a> {
             true
         });
 
+        // Pinned bottles are never removed as orphans, so they become roots
+        for (&bottle, ref_count) in &mut ref_counts {
+            if *ref_count == 0 && self.pinned.contains(bottle.name) {
+                self.dependencies.insert((None, bottle));
+                *ref_count += 1;
+            }
+        }
+
         let mut stack = Vec::new();
         for bottle in self.bottles.iter() {
             if ref_counts[bottle] == 0 {
@@ -1,17 +1,24 @@
-use std::{collections::BTreeMap, fs, os::unix, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    os::unix,
+    path::Path,
+    process,
+};
 
 use anyhow::Context;
 use data_encoding::HEXLOWER;
 use flate2::read::GzDecoder;
-use reqwest::blocking::Response;
+use reqwest::{blocking::Response, header::CONTENT_LENGTH};
 use serde::Deserialize;
 
 use crate::{
     cache::http_client,
     db::models::{DownloadedBottle, LinkedFile},
     dirs,
-    extract::{extract, validate::Validate},
+    extract::{ExtractedBottle, extract, validate::Validate},
     formulae::Formula,
+    links::LinkPlan,
     status::ProgressHandle,
 };
 
@@ -37,79 +44,128 @@ impl Formula {
             return Ok(bottle);
         }
 
-        let result = self.download_bottle_inner(progress);
+        let staging_path = dirs::staging_dir()?.join(format!("{}-{}", process::id(), self.name));
+        let result = self.download_bottle_inner(progress, &staging_path);
 
-        if result.is_err() {
-            if let Ok(Some(path)) = self.bottle_path() {
-                let _ = fs::remove_dir_all(&path);
-                if let Some(parent) = path.parent() {
-                    let _ = fs::remove_dir(parent);
-                }
-            }
-        }
+        // On success the bottle has been moved out, so only empty directories remain
+        let _ = fs::remove_dir_all(&staging_path);
 
         result.with_context(|| format!("Downloading {} {}", self.name, self.versions.stable))
     }
 
-    /// Expects the bottle to not already be downloaded and will not clean up if
-    /// the download fails.
-    fn download_bottle_inner(&self, progress: &ProgressHandle) -> anyhow::Result<DownloadedBottle> {
+    /// Downloads the bottle again into the directory of `bottle`, e.g. after
+    /// the directory was deleted. Its files and links are already recorded in
+    /// the DB, so they become valid again once the directory is back.
+    pub fn redownload_bottle(
+        &self,
+        bottle: &DownloadedBottle,
+        progress: &ProgressHandle,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.versions.stable == bottle.version(),
+            "{} {} is no longer available to download",
+            bottle.name(),
+            bottle.version(),
+        );
+
+        let staging_path = dirs::staging_dir()?.join(format!("{}-{}", process::id(), self.name));
+        let result = self
+            .fetch_bottle(progress, &staging_path)
+            .and_then(|extracted| {
+                let path = dirs::bottles_dir()?.join(&extracted.path);
+                anyhow::ensure!(
+                    path == bottle.path(),
+                    "Bottle would be extracted to {} instead of {}",
+                    path.display(),
+                    bottle.path().display(),
+                );
+                move_into_place(&staging_path.join(&extracted.path), &path)
+            });
+        let _ = fs::remove_dir_all(&staging_path);
+
+        result.with_context(|| format!("Downloading {} {}", self.name, self.versions.stable))
+    }
+
+    fn download_bottle_inner(
+        &self,
+        progress: &ProgressHandle,
+        staging_path: &Path,
+    ) -> anyhow::Result<DownloadedBottle> {
+        let extracted = self.fetch_bottle(progress, staging_path)?;
+        let path = dirs::bottles_dir()?.join(&extracted.path);
+        move_into_place(&staging_path.join(&extracted.path), &path)?;
+
+        let bottle = DownloadedBottle::create(
+            &self.name,
+            &self.versions.stable,
+            &path,
+            self.keg_only_reason().as_deref(),
+            extracted.receipt.as_ref(),
+            &extracted.manifest,
+        )?;
+
+        Ok(bottle)
+    }
+
+    /// Extracts the bottle into `staging_path`, and only returns once the
+    /// download has been validated.
+    fn fetch_bottle(
+        &self,
+        progress: &ProgressHandle,
+        staging_path: &Path,
+    ) -> anyhow::Result<ExtractedBottle> {
         let file_metadata = self.bottle.stable.current_target()?;
 
         let mut raw_data = file_metadata
             .fetch()
             .context("Failed to fetch bottle archive")?;
         let tracked = progress.track(&mut raw_data);
-        let unzip = GzDecoder::new(tracked);
-        let path = extract(unzip, self)?;
+        let mut unzip = GzDecoder::new(tracked);
+        let extracted = extract(&mut unzip, self, staging_path)?;
+
+        // The tar reader stops at the end-of-archive marker, so make sure the
+        // checksum covers the whole download
+        io::copy(&mut unzip, &mut io::sink())?;
 
         raw_data
             .validate()
             .context("Failed to validate bottle download")?;
 
-        let bottle = DownloadedBottle::create(&self.name, &self.versions.stable, &path)?;
-
-        Ok(bottle)
+        Ok(extracted)
     }
+}
 
-    fn bottle_path(&self) -> anyhow::Result<Option<PathBuf>> {
-        let name = self.name.as_str();
-        let version = self.versions.stable.as_str();
-
-        let bottles_path = dirs::bottles_dir()?;
-        let parent_path = bottles_path.join(name);
-        if !parent_path.exists() {
-            return Ok(None);
-        }
-
-        let path = parent_path.join(version);
-        if path.exists() {
-            return Ok(Some(path));
-        }
-
-        // Sometimes the bottle directory has "_1" appended to the version
-        for child in fs::read_dir(parent_path)? {
-            let child = child?;
-            let file_name = child.file_name();
-            let file_name = file_name.to_str().context("Invalid file name")?;
-            if file_name.starts_with(version) && file_name[version.len()..].starts_with('_') {
-                return Ok(Some(child.path()));
-            }
-        }
-
-        Ok(None)
-    }
+/// Moves a validated bottle out of the staging dir and into the bottles dir.
+fn move_into_place(staged: &Path, path: &Path) -> anyhow::Result<()> {
+    // Left behind by a bottle that was never recorded in the DB, which
+    // rebuild-db can recover, so it mustn't be replaced
+    anyhow::ensure!(
+        !path.exists(),
+        "{} already exists but is not in the DB. Run `chug rebuild-db` to add it",
+        path.display(),
+    );
+    fs::create_dir_all(path.parent().context("Bottle path has no parent")?)?;
+    fs::rename(staged, path).context("Failed to move bottle into place")?;
+
+    Ok(())
 }
 
 impl Bottle {
     pub fn current_target(&self) -> anyhow::Result<&FileMetadata> {
-        let target = crate::target::Target::current_str()?;
+        self.target(crate::target::Target::current_str()?)
+    }
+
+    fn target(&self, target: &str) -> anyhow::Result<&FileMetadata> {
         if let Some(file) = self.files.get(target) {
             Ok(file)
         } else if let Some(file) = self.files.get("all") {
             Ok(file)
         } else {
-            anyhow::bail!("No bottle for target: {target}");
+            let available = self.files.keys().cloned().collect::<Vec<_>>();
+            anyhow::bail!(
+                "No bottle for this platform ({target}). Available targets: {}",
+                available.join(", "),
+            );
         }
     }
 }
@@ -132,10 +188,51 @@ impl FileMetadata {
 
         Ok(reader)
     }
+
+    /// Returns the size of the bottle archive, without downloading it.
+    pub fn download_size(&self) -> anyhow::Result<u64> {
+        let response = http_client().head(&self.url).bearer_auth("QQ==").send()?;
+        anyhow::ensure!(
+            response.status().is_success(),
+            "Failed to fetch bottle size. Response code was: {}",
+            response.status(),
+        );
+
+        let size = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .context("Bottle response has no content length")?
+            .to_str()?
+            .parse()?;
+
+        Ok(size)
+    }
 }
 
 impl DownloadedBottle {
-    pub fn link(&self) -> anyhow::Result<()> {
+    /// Returns the downloaded bottles for each formula name, failing if any
+    /// are not downloaded.
+    pub fn get_by_names(names: &[String]) -> anyhow::Result<Vec<DownloadedBottle>> {
+        DownloadedBottle::select_by_names(&DownloadedBottle::get_installed()?, names)
+    }
+
+    /// Returns the bottles in `bottles` for each formula name, failing if any
+    /// are missing.
+    pub fn select_by_names(
+        bottles: &[DownloadedBottle],
+        names: &[String],
+    ) -> anyhow::Result<Vec<DownloadedBottle>> {
+        let mut selected = Vec::new();
+        for name in names {
+            let len = selected.len();
+            selected.extend(bottles.iter().filter(|b| b.name() == name).cloned());
+            anyhow::ensure!(selected.len() > len, "No downloaded bottle named {name}");
+        }
+
+        Ok(selected)
+    }
+
+    pub fn link(&self, plan: &LinkPlan) -> anyhow::Result<()> {
         println!("Linking {} {}...", self.name(), self.version());
 
         let opt_dir = dirs::opt_dir()?.join(self.name());
@@ -144,30 +241,21 @@ impl DownloadedBottle {
         }
         unix::fs::symlink(self.path(), &opt_dir)?;
 
-        let bin_dir = dirs::bin_dir()?;
-        let bottle_bin_dir = PathBuf::from(self.path()).join("bin");
-
-        if bottle_bin_dir.exists() {
-            for entry in fs::read_dir(bottle_bin_dir)? {
-                let entry = entry?;
-                let entry_path = entry.path();
-                let entry_name = entry.file_name();
-                let dest = bin_dir.join(entry_name);
-
-                if dest.exists() {
-                    let Ok(existing_path) = fs::read_link(&dest) else {
-                        continue;
-                    };
-                    if !existing_path.starts_with(dirs::bottles_dir()?) {
-                        continue;
-                    }
-                    fs::remove_file(&dest)?;
-                }
-
-                LinkedFile::create(&dest, self)?;
-
-                unix::fs::symlink(&entry_path, &dest)?;
-            }
+        self.link_files(plan)?;
+        if plan.is_unlinked(self) {
+            println!(
+                "Not linking files for {} as it was unlinked with `chug unlink`",
+                self.name(),
+            );
+        }
+        if let Some(reason) = plan.keg_only_reason(self) {
+            println!(
+                "{name} is keg-only, which means it was not linked into {},\n\
+                because {reason}.\n\
+                Run `chug add --force-link {name}` to link it anyway.",
+                dirs::bin_dir()?.display(),
+                name = self.name(),
+            );
         }
 
         // Replicate some important post-install scripts
@@ -183,30 +271,78 @@ impl DownloadedBottle {
         Ok(())
     }
 
+    /// Links the files, such as executables and man pages, that `plan`
+    /// assigns to the bottle, replacing whatever was there before. Links that
+    /// the plan no longer assigns to it, e.g. after losing a conflict, are
+    /// removed.
+    pub fn link_files(&self, plan: &LinkPlan) -> anyhow::Result<()> {
+        let assigned = plan
+            .links_for(self)
+            .map(|(_, dest)| dest)
+            .collect::<BTreeSet<_>>();
+        for linked_file in self.linked_files()? {
+            if !assigned.contains(linked_file.path()) {
+                self.remove_linked_file(&linked_file)?;
+            }
+        }
+
+        for (source, dest) in plan.links_for(self) {
+            if !fs::read_link(dest).is_ok_and(|existing| existing == source) {
+                if let Ok(metadata) = fs::symlink_metadata(dest) {
+                    anyhow::ensure!(
+                        !metadata.is_dir(),
+                        "Cannot replace the directory {} with a link",
+                        dest.display(),
+                    );
+                    fs::remove_file(dest)?;
+                }
+                fs::create_dir_all(dest.parent().context("Link path has no parent")?)?;
+                unix::fs::symlink(source, dest)?;
+            }
+
+            // Only recorded once the link exists, so that chug never claims a
+            // file it failed to replace
+            LinkedFile::create(dest, self)?;
+        }
+
+        Ok(())
+    }
+
     pub fn unlink(&self) -> anyhow::Result<()> {
         println!("Unlinking {} {}...", self.name(), self.version());
 
         let opt_dir = dirs::opt_dir()?.join(self.name());
-        if let Ok(linked_path) = fs::read_link(&opt_dir) {
-            if linked_path == self.path() {
-                fs::remove_file(&opt_dir)?;
-            }
+        if let Ok(linked_path) = fs::read_link(&opt_dir)
+            && linked_path == self.path()
+        {
+            fs::remove_file(&opt_dir)?;
         }
 
-        let bottle_dir = self.path();
-        for linked_file in self.linked_files()? {
-            if let Ok(linked_path) = fs::read_link(linked_file.path()) {
-                if linked_path.starts_with(bottle_dir) {
-                    fs::remove_file(linked_file.path())?;
-                }
-            }
+        self.unlink_files()
+    }
 
-            linked_file.delete()?;
+    /// Removes the bottle's links, such as those in the bin dir, but leaves
+    /// its opt link in place for any dependents.
+    pub fn unlink_files(&self) -> anyhow::Result<()> {
+        for linked_file in self.linked_files()? {
+            self.remove_linked_file(&linked_file)?;
         }
 
         Ok(())
     }
 
+    /// Removes the link if it still points into the bottle, and forgets it
+    /// either way.
+    fn remove_linked_file(&self, linked_file: &LinkedFile) -> anyhow::Result<()> {
+        if let Ok(linked_path) = fs::read_link(linked_file.path())
+            && linked_path.starts_with(self.path())
+        {
+            fs::remove_file(linked_file.path())?;
+        }
+
+        linked_file.delete()
+    }
+
     pub fn remove(&self) -> anyhow::Result<()> {
         println!("Deleting {} {}...", self.name(), self.version());
 
@@ -220,3 +356,26 @@ impl DownloadedBottle {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Bottle;
+
+    #[test]
+    fn missing_target_lists_available_targets() {
+        let bottle = serde_json::from_str::<Bottle>(
+            r#"{"files": {
+                "arm64_sequoia": {"url": "", "sha256": ""},
+                "x86_64_linux": {"url": "", "sha256": ""}
+            }}"#,
+        )
+        .unwrap();
+
+        assert!(bottle.target("x86_64_linux").is_ok());
+        let err = bottle.target("arm64_linux").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No bottle for this platform (arm64_linux). Available targets: arm64_sequoia, x86_64_linux",
+        );
+    }
+}
@@ -1,11 +1,22 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
 use diesel::{prelude::*, sqlite::Sqlite};
 
-use crate::db::{
-    connection,
-    schema::{dependencies, downloaded_bottles, linked_files},
+use crate::{
+    db::{
+        connection,
+        schema::{
+            bottle_files, dependencies, downloaded_bottles, forced_links, generation_dependencies,
+            generation_links, generations, install_receipts, journal, link_preferences,
+            linked_files, pinned_formulae, runtime_dependencies, unlinked_formulae,
+        },
+    },
+    manifest::{FileKind, ManifestEntry},
+    receipt::InstallReceipt,
 };
 
 #[derive(Clone, Debug, Queryable, Selectable)]
@@ -16,6 +27,7 @@ pub struct DownloadedBottle {
     name: String,
     version: String,
     path: String,
+    keg_only_reason: Option<String>,
 }
 
 #[derive(Debug, Insertable)]
@@ -25,6 +37,7 @@ struct NewDownloadedBottle<'a> {
     name: &'a str,
     version: &'a str,
     path: &'a str,
+    keg_only_reason: Option<&'a str>,
 }
 
 #[derive(Debug, Queryable, Selectable)]
@@ -43,6 +56,35 @@ struct NewLinkedFile<'a> {
     bottle_id: i32,
 }
 
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = forced_links)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct ForcedLink {
+    name: String,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = pinned_formulae)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct PinnedFormula {
+    name: String,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = unlinked_formulae)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct UnlinkedFormula {
+    name: String,
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = link_preferences)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct LinkPreference {
+    name: String,
+    formula: String,
+}
+
 #[derive(Debug, Queryable, Selectable, Insertable)]
 #[diesel(table_name = dependencies)]
 #[diesel(check_for_backend(Sqlite))]
@@ -51,18 +93,127 @@ pub struct Dependency {
     dependency_id: i32,
 }
 
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = generations)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct Generation {
+    id: i32,
+    created_at: String,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = generation_links)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct GenerationLink {
+    bottle_id: i32,
+    path: String,
+    target: String,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = generation_links)]
+#[diesel(check_for_backend(Sqlite))]
+struct NewGenerationLink<'a> {
+    generation_id: i32,
+    bottle_id: i32,
+    path: &'a str,
+    target: &'a str,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = journal)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct Journal {
+    generation_id: i32,
+    committed: bool,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = bottle_files)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct BottleFile {
+    path: String,
+    kind: String,
+    mode: i32,
+    size: i64,
+    sha256: Option<String>,
+    relocated: bool,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = bottle_files)]
+#[diesel(check_for_backend(Sqlite))]
+struct NewBottleFile<'a> {
+    bottle_id: i32,
+    path: &'a str,
+    kind: &'a str,
+    mode: i32,
+    size: i64,
+    sha256: Option<&'a str>,
+    relocated: bool,
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = install_receipts)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct Receipt {
+    bottle_id: i32,
+    homebrew_version: Option<String>,
+    tap: Option<String>,
+    compiler: Option<String>,
+    built_as_bottle: bool,
+    installed_on_request: bool,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = runtime_dependencies)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct RuntimeDependency {
+    bottle_id: i32,
+    full_name: String,
+    declared_directly: Option<bool>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = runtime_dependencies)]
+#[diesel(check_for_backend(Sqlite))]
+struct NewRuntimeDependency<'a> {
+    bottle_id: i32,
+    full_name: &'a str,
+    version: &'a str,
+    revision: i32,
+    declared_directly: Option<bool>,
+}
+
 impl DownloadedBottle {
-    pub fn create(name: &str, version: &str, path: &Path) -> anyhow::Result<DownloadedBottle> {
+    pub fn create(
+        name: &str,
+        version: &str,
+        path: &Path,
+        keg_only_reason: Option<&str>,
+        receipt: Option<&InstallReceipt>,
+        manifest: &[ManifestEntry],
+    ) -> anyhow::Result<DownloadedBottle> {
         let mut db = connection()?;
+        let path = path.to_str().context("Installed bottle path is non-utf8")?;
 
-        let result = diesel::insert_into(downloaded_bottles::table)
-            .values(NewDownloadedBottle {
-                name,
-                version,
-                path: path.to_str().context("Installed bottle path is non-utf8")?,
-            })
-            .returning(DownloadedBottle::as_returning())
-            .get_result(&mut *db)?;
+        let result = db.transaction::<_, anyhow::Error, _>(|db| {
+            let bottle = diesel::insert_into(downloaded_bottles::table)
+                .values(NewDownloadedBottle {
+                    name,
+                    version,
+                    path,
+                    keg_only_reason,
+                })
+                .returning(DownloadedBottle::as_returning())
+                .get_result(db)?;
+            if let Some(receipt) = receipt {
+                Receipt::insert(db, &bottle, receipt)?;
+            }
+            bottle.insert_manifest(db, manifest)?;
+
+            Ok(bottle)
+        })?;
 
         Ok(result)
     }
@@ -87,7 +238,7 @@ impl DownloadedBottle {
         }
     }
 
-    fn get_by_id(id: i32) -> anyhow::Result<DownloadedBottle> {
+    pub fn get_by_id(id: i32) -> anyhow::Result<DownloadedBottle> {
         use downloaded_bottles::dsl;
 
         let mut db = connection()?;
@@ -113,6 +264,22 @@ impl DownloadedBottle {
         Ok(results)
     }
 
+    /// Returns the bottles in the current generation, i.e. excluding previous
+    /// versions that are kept for `chug rollback`.
+    pub fn get_installed() -> anyhow::Result<Vec<DownloadedBottle>> {
+        use downloaded_bottles::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::downloaded_bottles
+            .filter(dsl::id.eq_any(dependencies::table.select(dependencies::dependency_id)))
+            .order((dsl::name, dsl::version))
+            .select(DownloadedBottle::as_select())
+            .load(&mut *db)?;
+
+        Ok(result)
+    }
+
     pub fn delete(&self) -> anyhow::Result<()> {
         use downloaded_bottles::dsl;
 
@@ -141,6 +308,10 @@ impl DownloadedBottle {
         self.path.as_ref()
     }
 
+    pub fn keg_only_reason(&self) -> Option<&str> {
+        self.keg_only_reason.as_deref()
+    }
+
     pub fn linked_files(&self) -> anyhow::Result<Vec<LinkedFile>> {
         use linked_files::dsl;
 
@@ -153,6 +324,157 @@ impl DownloadedBottle {
 
         Ok(results)
     }
+
+    pub fn manifest(&self) -> anyhow::Result<Vec<BottleFile>> {
+        use bottle_files::dsl;
+
+        let mut db = connection()?;
+
+        let results = dsl::bottle_files
+            .filter(dsl::bottle_id.eq(self.id))
+            .order(dsl::path)
+            .select(BottleFile::as_select())
+            .load(&mut *db)?;
+
+        Ok(results)
+    }
+
+    fn insert_manifest(
+        &self,
+        db: &mut SqliteConnection,
+        manifest: &[ManifestEntry],
+    ) -> anyhow::Result<()> {
+        let new_files = manifest
+            .iter()
+            .map(|entry| {
+                Ok(NewBottleFile {
+                    bottle_id: self.id,
+                    path: entry
+                        .path
+                        .to_str()
+                        .context("Bottle file path is non-utf8")?,
+                    kind: entry.kind.as_str(),
+                    mode: entry.mode as i32,
+                    size: entry.size as i64,
+                    sha256: entry.sha256.as_deref(),
+                    relocated: entry.relocated,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // SQLite limits the number of parameters in a single statement
+        for chunk in new_files.chunks(1000) {
+            diesel::insert_into(bottle_files::table)
+                .values(chunk)
+                .execute(db)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BottleFile {
+    /// Relative to the bottle's path.
+    pub fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    pub fn kind(&self) -> anyhow::Result<FileKind> {
+        self.kind.parse()
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.mode as u32
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size as u64
+    }
+
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    pub fn relocated(&self) -> bool {
+        self.relocated
+    }
+}
+
+impl Receipt {
+    fn insert(
+        db: &mut SqliteConnection,
+        bottle: &DownloadedBottle,
+        receipt: &InstallReceipt,
+    ) -> anyhow::Result<()> {
+        diesel::insert_into(install_receipts::table)
+            .values(Receipt {
+                bottle_id: bottle.id,
+                homebrew_version: receipt.homebrew_version.clone(),
+                tap: receipt.source.as_ref().and_then(|s| s.tap.clone()),
+                compiler: receipt.compiler.clone(),
+                built_as_bottle: receipt.built_as_bottle,
+                installed_on_request: receipt.installed_on_request,
+            })
+            .execute(db)?;
+
+        let new_dependencies = receipt
+            .runtime_dependencies
+            .iter()
+            .flatten()
+            .map(|dependency| NewRuntimeDependency {
+                bottle_id: bottle.id,
+                full_name: &dependency.full_name,
+                version: &dependency.version,
+                revision: dependency.revision,
+                declared_directly: dependency.declared_directly,
+            })
+            .collect::<Vec<_>>();
+        diesel::insert_into(runtime_dependencies::table)
+            .values(&new_dependencies)
+            .execute(db)?;
+
+        Ok(())
+    }
+
+    /// Returns the ids of the bottles that have an install receipt.
+    pub fn bottle_ids() -> anyhow::Result<Vec<i32>> {
+        use install_receipts::dsl;
+
+        let mut db = connection()?;
+
+        let results = dsl::install_receipts
+            .select(dsl::bottle_id)
+            .load(&mut *db)?;
+
+        Ok(results)
+    }
+}
+
+impl RuntimeDependency {
+    pub fn get_all() -> anyhow::Result<Vec<RuntimeDependency>> {
+        use runtime_dependencies::dsl;
+
+        let mut db = connection()?;
+
+        let results = dsl::runtime_dependencies
+            .order((dsl::bottle_id, dsl::full_name))
+            .select(RuntimeDependency::as_select())
+            .load(&mut *db)?;
+
+        Ok(results)
+    }
+
+    pub fn bottle_id(&self) -> i32 {
+        self.bottle_id
+    }
+
+    pub fn full_name(&self) -> &str {
+        &self.full_name
+    }
+
+    pub fn declared_directly(&self) -> Option<bool> {
+        self.declared_directly
+    }
 }
 
 impl LinkedFile {
@@ -164,8 +486,10 @@ impl LinkedFile {
                 path: path.to_str().context("Linked file path is non-utf8")?,
                 bottle_id: bottle.id,
             })
+            // When upgrading, the new bottle takes over the old bottle's links
             .on_conflict(linked_files::dsl::path)
-            .do_nothing()
+            .do_update()
+            .set(linked_files::dsl::bottle_id.eq(bottle.id))
             .execute(&mut *db)?;
 
         Ok(())
@@ -188,6 +512,457 @@ impl LinkedFile {
     }
 }
 
+impl Generation {
+    /// Ordered from oldest to newest.
+    pub fn get_all() -> anyhow::Result<Vec<Generation>> {
+        use generations::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::generations
+            .order(dsl::id)
+            .select(Generation::as_select())
+            .load(&mut *db)?;
+
+        Ok(result)
+    }
+
+    /// Records the current dependencies and links. `links` maps each linked
+    /// file's path to its target.
+    pub fn create(links: &BTreeMap<PathBuf, PathBuf>) -> anyhow::Result<Generation> {
+        let mut db = connection()?;
+
+        let result = db.transaction::<_, anyhow::Error, _>(|db| {
+            let generation = diesel::insert_into(generations::table)
+                .default_values()
+                .returning(Generation::as_returning())
+                .get_result(db)?;
+
+            let dependencies = dependencies::table
+                .select(Dependency::as_select())
+                .load(db)?;
+            let new_dependencies = dependencies
+                .iter()
+                .map(|dependency| {
+                    (
+                        generation_dependencies::generation_id.eq(generation.id),
+                        generation_dependencies::dependent_id.eq(dependency.dependent_id),
+                        generation_dependencies::dependency_id.eq(dependency.dependency_id),
+                    )
+                })
+                .collect::<Vec<_>>();
+            diesel::insert_into(generation_dependencies::table)
+                .values(&new_dependencies)
+                .execute(db)?;
+
+            let linked_files = linked_files::table
+                .select((linked_files::path, linked_files::bottle_id))
+                .load::<(String, i32)>(db)?;
+            let mut new_links = Vec::new();
+            for (path, bottle_id) in &linked_files {
+                let Some(target) = links.get(Path::new(path)) else {
+                    continue;
+                };
+                new_links.push(NewGenerationLink {
+                    generation_id: generation.id,
+                    bottle_id: *bottle_id,
+                    path,
+                    target: target.to_str().context("Link target is non-utf8")?,
+                });
+            }
+            diesel::insert_into(generation_links::table)
+                .values(&new_links)
+                .execute(db)?;
+
+            Ok(generation)
+        })?;
+
+        Ok(result)
+    }
+
+    /// Makes the generation's dependencies and links current in the DB. The
+    /// links themselves must be updated separately.
+    pub fn restore(&self) -> anyhow::Result<()> {
+        let mut db = connection()?;
+
+        db.transaction::<(), anyhow::Error, _>(|db| {
+            let dependencies = generation_dependencies::table
+                .filter(generation_dependencies::generation_id.eq(self.id))
+                .select((
+                    generation_dependencies::dependent_id,
+                    generation_dependencies::dependency_id,
+                ))
+                .load::<Dependency>(db)?;
+            diesel::delete(dependencies::table).execute(db)?;
+            diesel::insert_into(dependencies::table)
+                .values(&dependencies)
+                .execute(db)?;
+
+            let links = generation_links::table
+                .filter(generation_links::generation_id.eq(self.id))
+                .select(GenerationLink::as_select())
+                .load(db)?;
+            diesel::delete(linked_files::table).execute(db)?;
+            let new_linked_files = links
+                .iter()
+                .map(|link| NewLinkedFile {
+                    path: &link.path,
+                    bottle_id: link.bottle_id,
+                })
+                .collect::<Vec<_>>();
+            diesel::insert_into(linked_files::table)
+                .values(&new_linked_files)
+                .execute(db)?;
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    pub fn delete(&self) -> anyhow::Result<()> {
+        use generations::dsl;
+
+        let mut db = connection()?;
+
+        diesel::delete(dsl::generations)
+            .filter(dsl::id.eq(self.id))
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    /// Returns the ids of the bottles in this generation.
+    pub fn bottle_ids(&self) -> anyhow::Result<Vec<i32>> {
+        use generation_dependencies::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::generation_dependencies
+            .filter(dsl::generation_id.eq(self.id))
+            .select(dsl::dependency_id)
+            .distinct()
+            .load(&mut *db)?;
+
+        Ok(result)
+    }
+
+    pub fn links(&self) -> anyhow::Result<Vec<GenerationLink>> {
+        use generation_links::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::generation_links
+            .filter(dsl::generation_id.eq(self.id))
+            .select(GenerationLink::as_select())
+            .load(&mut *db)?;
+
+        Ok(result)
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn created_at(&self) -> &str {
+        &self.created_at
+    }
+}
+
+impl GenerationLink {
+    pub fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    pub fn target(&self) -> &Path {
+        self.target.as_ref()
+    }
+}
+
+impl Journal {
+    pub fn get() -> anyhow::Result<Option<Journal>> {
+        use journal::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::journal
+            .select(Journal::as_select())
+            .first(&mut *db)
+            .optional()?;
+
+        Ok(result)
+    }
+
+    /// Records that an action has started, which can be undone by restoring
+    /// `generation`.
+    pub fn begin(generation: &Generation) -> anyhow::Result<Journal> {
+        use journal::dsl;
+
+        let mut db = connection()?;
+
+        let result = diesel::insert_into(dsl::journal)
+            .values((dsl::id.eq(1), dsl::generation_id.eq(generation.id)))
+            .returning(Journal::as_returning())
+            .get_result(&mut *db)
+            .context("Another action is already in progress")?;
+
+        Ok(result)
+    }
+
+    /// Records that the action can no longer be undone, and must be finished
+    /// instead.
+    pub fn commit(&mut self) -> anyhow::Result<()> {
+        use journal::dsl;
+
+        let mut db = connection()?;
+
+        diesel::update(dsl::journal)
+            .set(dsl::committed.eq(true))
+            .execute(&mut *db)?;
+        self.committed = true;
+
+        Ok(())
+    }
+
+    pub fn delete(&self) -> anyhow::Result<()> {
+        use journal::dsl;
+
+        let mut db = connection()?;
+
+        diesel::delete(dsl::journal).execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    pub fn generation(&self) -> anyhow::Result<Generation> {
+        use generations::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::generations
+            .filter(dsl::id.eq(self.generation_id))
+            .select(Generation::as_select())
+            .first(&mut *db)?;
+
+        Ok(result)
+    }
+
+    pub fn is_committed(&self) -> bool {
+        self.committed
+    }
+}
+
+impl ForcedLink {
+    pub fn get_all() -> anyhow::Result<Vec<ForcedLink>> {
+        use forced_links::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::forced_links
+            .select(ForcedLink::as_select())
+            .load(&mut *db)?;
+
+        Ok(result)
+    }
+
+    pub fn replace_all<'a>(names: impl Iterator<Item = &'a str>) -> anyhow::Result<()> {
+        use forced_links::dsl;
+
+        let mut db = connection()?;
+
+        db.transaction::<(), anyhow::Error, _>(|db| {
+            diesel::delete(dsl::forced_links).execute(db)?;
+
+            let new_forced_links = names.map(|name| dsl::name.eq(name)).collect::<Vec<_>>();
+            diesel::insert_into(dsl::forced_links)
+                .values(&new_forced_links)
+                .execute(db)?;
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    pub fn create(name: &str) -> anyhow::Result<()> {
+        use forced_links::dsl;
+
+        let mut db = connection()?;
+
+        diesel::insert_into(dsl::forced_links)
+            .values(dsl::name.eq(name))
+            .on_conflict_do_nothing()
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PinnedFormula {
+    pub fn get_all() -> anyhow::Result<Vec<PinnedFormula>> {
+        use pinned_formulae::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::pinned_formulae
+            .select(PinnedFormula::as_select())
+            .load(&mut *db)?;
+
+        Ok(result)
+    }
+
+    pub fn create(name: &str) -> anyhow::Result<()> {
+        use pinned_formulae::dsl;
+
+        let mut db = connection()?;
+
+        diesel::insert_into(dsl::pinned_formulae)
+            .values(dsl::name.eq(name))
+            .on_conflict_do_nothing()
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    pub fn delete(&self) -> anyhow::Result<()> {
+        use pinned_formulae::dsl;
+
+        let mut db = connection()?;
+
+        diesel::delete(dsl::pinned_formulae)
+            .filter(dsl::name.eq(&self.name))
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    /// Forgets formulae that are no longer downloaded.
+    pub fn delete_except(names: &[&str]) -> anyhow::Result<()> {
+        use pinned_formulae::dsl;
+
+        let mut db = connection()?;
+
+        diesel::delete(dsl::pinned_formulae)
+            .filter(dsl::name.ne_all(names))
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl UnlinkedFormula {
+    pub fn get_all() -> anyhow::Result<Vec<UnlinkedFormula>> {
+        use unlinked_formulae::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::unlinked_formulae
+            .select(UnlinkedFormula::as_select())
+            .load(&mut *db)?;
+
+        Ok(result)
+    }
+
+    pub fn create(name: &str) -> anyhow::Result<()> {
+        use unlinked_formulae::dsl;
+
+        let mut db = connection()?;
+
+        diesel::insert_into(dsl::unlinked_formulae)
+            .values(dsl::name.eq(name))
+            .on_conflict_do_nothing()
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    pub fn delete(name: &str) -> anyhow::Result<()> {
+        use unlinked_formulae::dsl;
+
+        let mut db = connection()?;
+
+        diesel::delete(dsl::unlinked_formulae)
+            .filter(dsl::name.eq(name))
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    /// Forgets formulae that are no longer downloaded.
+    pub fn delete_except(names: &[&str]) -> anyhow::Result<()> {
+        use unlinked_formulae::dsl;
+
+        let mut db = connection()?;
+
+        diesel::delete(dsl::unlinked_formulae)
+            .filter(dsl::name.ne_all(names))
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl LinkPreference {
+    pub fn get_all() -> anyhow::Result<Vec<LinkPreference>> {
+        use link_preferences::dsl;
+
+        let mut db = connection()?;
+
+        let result = dsl::link_preferences
+            .select(LinkPreference::as_select())
+            .load(&mut *db)?;
+
+        Ok(result)
+    }
+
+    pub fn set(name: &str, formula: &str) -> anyhow::Result<()> {
+        use link_preferences::dsl;
+
+        let mut db = connection()?;
+
+        diesel::insert_into(dsl::link_preferences)
+            .values((dsl::name.eq(name), dsl::formula.eq(formula)))
+            .on_conflict(dsl::name)
+            .do_update()
+            .set(dsl::formula.eq(formula))
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    pub fn clear(name: &str) -> anyhow::Result<()> {
+        use link_preferences::dsl;
+
+        let mut db = connection()?;
+
+        diesel::delete(dsl::link_preferences)
+            .filter(dsl::name.eq(name))
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn formula(&self) -> &str {
+        &self.formula
+    }
+}
+
 impl Dependency {
     pub fn get_all() -> anyhow::Result<Vec<Dependency>> {
         use dependencies::dsl;
@@ -227,6 +1002,19 @@ impl Dependency {
         Ok(())
     }
 
+    pub fn delete(&self) -> anyhow::Result<()> {
+        use dependencies::dsl;
+
+        let mut db = connection()?;
+
+        diesel::delete(dsl::dependencies)
+            .filter(dsl::dependent_id.is(self.dependent_id))
+            .filter(dsl::dependency_id.eq(self.dependency_id))
+            .execute(&mut *db)?;
+
+        Ok(())
+    }
+
     /// Returns `None` if the bottle was manually added, i.e. if it is a root.
     pub fn dependent(&self) -> anyhow::Result<Option<DownloadedBottle>> {
         if let Some(id) = self.dependent_id {
@@ -1,5 +1,18 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    bottle_files (id) {
+        id -> Integer,
+        bottle_id -> Integer,
+        path -> Text,
+        kind -> Text,
+        mode -> Integer,
+        size -> BigInt,
+        sha256 -> Nullable<Text>,
+        relocated -> Bool,
+    }
+}
+
 diesel::table! {
     dependencies (id) {
         id -> Integer,
@@ -14,6 +27,65 @@ diesel::table! {
         name -> Text,
         version -> Text,
         path -> Text,
+        keg_only_reason -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    forced_links (name) {
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    generation_dependencies (id) {
+        id -> Integer,
+        generation_id -> Integer,
+        dependent_id -> Nullable<Integer>,
+        dependency_id -> Integer,
+    }
+}
+
+diesel::table! {
+    generation_links (id) {
+        id -> Integer,
+        generation_id -> Integer,
+        bottle_id -> Integer,
+        path -> Text,
+        target -> Text,
+    }
+}
+
+diesel::table! {
+    generations (id) {
+        id -> Integer,
+        created_at -> Text,
+    }
+}
+
+diesel::table! {
+    install_receipts (bottle_id) {
+        bottle_id -> Integer,
+        homebrew_version -> Nullable<Text>,
+        tap -> Nullable<Text>,
+        compiler -> Nullable<Text>,
+        built_as_bottle -> Bool,
+        installed_on_request -> Bool,
+    }
+}
+
+diesel::table! {
+    journal (id) {
+        id -> Integer,
+        generation_id -> Integer,
+        committed -> Bool,
+    }
+}
+
+diesel::table! {
+    link_preferences (name) {
+        name -> Text,
+        formula -> Text,
     }
 }
 
@@ -25,10 +97,51 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    pinned_formulae (name) {
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    runtime_dependencies (id) {
+        id -> Integer,
+        bottle_id -> Integer,
+        full_name -> Text,
+        version -> Text,
+        revision -> Integer,
+        declared_directly -> Nullable<Bool>,
+    }
+}
+
+diesel::table! {
+    unlinked_formulae (name) {
+        name -> Text,
+    }
+}
+
+diesel::joinable!(bottle_files -> downloaded_bottles (bottle_id));
+diesel::joinable!(generation_dependencies -> generations (generation_id));
+diesel::joinable!(generation_links -> downloaded_bottles (bottle_id));
+diesel::joinable!(generation_links -> generations (generation_id));
+diesel::joinable!(install_receipts -> downloaded_bottles (bottle_id));
+diesel::joinable!(journal -> generations (generation_id));
 diesel::joinable!(linked_files -> downloaded_bottles (bottle_id));
+diesel::joinable!(runtime_dependencies -> downloaded_bottles (bottle_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    bottle_files,
     dependencies,
     downloaded_bottles,
+    forced_links,
+    generation_dependencies,
+    generation_links,
+    generations,
+    install_receipts,
+    journal,
+    link_preferences,
     linked_files,
+    pinned_formulae,
+    runtime_dependencies,
+    unlinked_formulae,
 );
@@ -1,6 +1,7 @@
 use std::{
-    env, fs,
+    fs,
     path::{Path, PathBuf},
+    process::{self, Command, Stdio},
 };
 
 use anyhow::Context;
@@ -9,17 +10,34 @@ const PROGRAM_NAME: &str = "chug";
 
 fn home_dir() -> anyhow::Result<&'static Path> {
     let path = cache!(PathBuf).get_or_init(|| {
-        let path = env::var("HOME").context("$HOME not set")?;
+        let path = var("HOME").context("$HOME not set")?;
         Ok(path.into())
     })?;
     Ok(path)
 }
 
+/// Reads the environment variables that locate the dirs.
+#[cfg(not(test))]
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Unit tests get a home of their own from `testing::home`, so they never
+/// touch real bottles. The XDG variables are ignored, as they would point
+/// outside of it.
+#[cfg(test)]
+fn var(name: &str) -> Option<String> {
+    let home = crate::testing::HOME
+        .get()
+        .expect("Unit tests must call `testing::home` before using dirs");
+    (name == "HOME").then(|| home.to_string_lossy().into_owned())
+}
+
 pub fn bin_dir() -> anyhow::Result<&'static Path> {
     let path = cache!(PathBuf).get_or_init(|| {
         // XDG_BIN_HOME is not a standard, but some people use it
         // https://gist.github.com/roalcantara/107ba66dfa3b9d023ac9329e639bc58c
-        let path = if let Ok(xdg_dir) = env::var("XDG_BIN_HOME") {
+        let path = if let Some(xdg_dir) = var("XDG_BIN_HOME") {
             PathBuf::from(xdg_dir)
         } else {
             home_dir()?.join(".local/bin")
@@ -34,7 +52,7 @@ pub fn bin_dir() -> anyhow::Result<&'static Path> {
 
 pub fn cache_dir() -> anyhow::Result<&'static Path> {
     let path = cache!(PathBuf).get_or_init(|| {
-        let mut path = if let Ok(xdg_dir) = env::var("XDG_CACHE_HOME") {
+        let mut path = if let Some(xdg_dir) = var("XDG_CACHE_HOME") {
             PathBuf::from(xdg_dir)
         } else {
             home_dir()?.join(".cache")
@@ -48,14 +66,21 @@ pub fn cache_dir() -> anyhow::Result<&'static Path> {
     Ok(path)
 }
 
-pub fn data_dir() -> anyhow::Result<&'static Path> {
+fn xdg_data_home() -> anyhow::Result<&'static Path> {
     let path = cache!(PathBuf).get_or_init(|| {
-        let mut path = if let Ok(xdg_dir) = env::var("XDG_DATA_HOME") {
+        let path = if let Some(xdg_dir) = var("XDG_DATA_HOME") {
             PathBuf::from(xdg_dir)
         } else {
             home_dir()?.join(".local/share")
         };
-        path.push(PROGRAM_NAME);
+        Ok(path)
+    })?;
+    Ok(path)
+}
+
+pub fn data_dir() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = xdg_data_home()?.join(PROGRAM_NAME);
 
         fs::create_dir_all(&path).expect("Could not create data dir");
 
@@ -75,6 +100,62 @@ pub fn bottles_dir() -> anyhow::Result<&'static Path> {
     Ok(path)
 }
 
+/// Returns the directories in the bottles dir that could contain a bottle,
+/// i.e. those of the form `$name/$version`.
+pub fn bottle_dirs() -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for name in fs::read_dir(bottles_dir()?)? {
+        let name = name?;
+        if !name.file_type()?.is_dir() {
+            continue;
+        }
+        for version in fs::read_dir(name.path())? {
+            let version = version?;
+            if version.file_type()?.is_dir() {
+                paths.push(version.path());
+            }
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+pub fn staging_dir() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = data_dir()?.join("staging");
+
+        fs::create_dir_all(&path).expect("Could not create staging dir");
+
+        // Downloads are staged in `$pid-$name`, so anything belonging to a
+        // process that is no longer running was left behind by an interrupted
+        // download
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            let pid = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.split_once('-'))
+                .and_then(|(pid, _)| pid.parse::<u32>().ok());
+            if pid.is_some_and(|pid| pid != process::id() && is_running(pid)) {
+                continue;
+            }
+            let _ = fs::remove_dir_all(entry.path());
+        }
+
+        Ok(path)
+    })?;
+    Ok(path)
+}
+
+fn is_running(pid: u32) -> bool {
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
 pub fn etc_dir() -> anyhow::Result<&'static Path> {
     let path = cache!(PathBuf).get_or_init(|| {
         let path = data_dir()?.join("etc");
@@ -97,6 +178,59 @@ pub fn opt_dir() -> anyhow::Result<&'static Path> {
     Ok(path)
 }
 
+// The following directories are only created once something is linked into
+// them, as most bottles don't provide anything for them.
+
+pub fn man_dir() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = xdg_data_home()?.join("man");
+        Ok(path)
+    })?;
+    Ok(path)
+}
+
+pub fn bash_completions_dir() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = xdg_data_home()?.join("bash-completion/completions");
+        Ok(path)
+    })?;
+    Ok(path)
+}
+
+pub fn fish_completions_dir() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = xdg_data_home()?.join("fish/vendor_completions.d");
+        Ok(path)
+    })?;
+    Ok(path)
+}
+
+/// zsh doesn't search this by default, so it has to be added to `$fpath`.
+pub fn zsh_completions_dir() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = xdg_data_home()?.join("zsh/site-functions");
+        Ok(path)
+    })?;
+    Ok(path)
+}
+
+/// Add this to `$PKG_CONFIG_PATH` to build against downloaded bottles.
+pub fn pkgconfig_dir() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = data_dir()?.join("lib/pkgconfig");
+        Ok(path)
+    })?;
+    Ok(path)
+}
+
+pub fn aclocal_dir() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = data_dir()?.join("share/aclocal");
+        Ok(path)
+    })?;
+    Ok(path)
+}
+
 pub fn db_file() -> anyhow::Result<&'static Path> {
     let path = cache!(PathBuf).get_or_init(|| {
         let path = data_dir()?.join("db.sqlite");
@@ -104,3 +238,11 @@ pub fn db_file() -> anyhow::Result<&'static Path> {
     })?;
     Ok(path)
 }
+
+pub fn lock_file() -> anyhow::Result<&'static Path> {
+    let path = cache!(PathBuf).get_or_init(|| {
+        let path = data_dir()?.join("lock");
+        Ok(path)
+    })?;
+    Ok(path)
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeSet,
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    db::models::{Dependency, DownloadedBottle, LinkedFile},
+    dirs,
+    formulae::Formula,
+    links,
+    status::Progress,
+};
+
+#[derive(Debug)]
+enum Problem {
+    /// A dependency refers to a bottle that is not in the DB.
+    StaleDependency(Dependency),
+    /// A linked file no longer links into the bottle that it is recorded for.
+    ForeignLinkedFile(LinkedFile, String),
+    /// A bottle is in the DB but its directory is missing.
+    MissingBottleDir(DownloadedBottle),
+    /// A symlink in the opt dir or a link dir points into a bottle that doesn't
+    /// exist.
+    DanglingLink(PathBuf),
+    /// A bottle directory that is not in the DB.
+    UntrackedBottleDir(PathBuf),
+    BinDirNotInPath(PathBuf),
+}
+
+/// Cross-checks the DB against the filesystem, optionally repairing any
+/// problems that are found.
+pub fn doctor(fix: bool) -> anyhow::Result<()> {
+    let problems = find_problems()?;
+    if problems.is_empty() {
+        println!("No problems found");
+        return Ok(());
+    }
+
+    let mut unfixed = 0;
+    for problem in &problems {
+        println!("{problem}");
+        if fix {
+            if problem.fix()? {
+                println!("  Fixed");
+            } else {
+                println!("  Cannot be fixed automatically");
+                unfixed += 1;
+            }
+        }
+    }
+
+    if !fix {
+        anyhow::bail!(
+            "Found {} problem(s). Run `chug doctor --fix` to repair them",
+            problems.len(),
+        );
+    }
+    anyhow::ensure!(
+        unfixed == 0,
+        "{unfixed} problem(s) could not be fixed automatically"
+    );
+
+    Ok(())
+}
+
+/// Problems are returned in the order they should be fixed in.
+fn find_problems() -> anyhow::Result<Vec<Problem>> {
+    let bottles = DownloadedBottle::get_all()?;
+    let bottle_ids = bottles.iter().map(|b| b.id()).collect::<BTreeSet<_>>();
+    let bottle_paths = bottles.iter().map(|b| b.path()).collect::<BTreeSet<_>>();
+
+    let mut problems = Vec::new();
+
+    for dependency in Dependency::get_all()? {
+        if dependency
+            .dependent_id()
+            .is_some_and(|id| !bottle_ids.contains(&id))
+            || !bottle_ids.contains(&dependency.dependency_id())
+        {
+            problems.push(Problem::StaleDependency(dependency));
+        }
+    }
+
+    for bottle in &bottles {
+        for linked_file in bottle.linked_files()? {
+            let links_to_bottle = fs::read_link(linked_file.path())
+                .is_ok_and(|target| target.starts_with(bottle.path()));
+            if !links_to_bottle {
+                problems.push(Problem::ForeignLinkedFile(
+                    linked_file,
+                    format!("{} {}", bottle.name(), bottle.version()),
+                ));
+            }
+        }
+    }
+
+    for bottle in &bottles {
+        if !bottle.path().is_dir() {
+            problems.push(Problem::MissingBottleDir(bottle.clone()));
+        }
+    }
+
+    let mut dirs_with_links = links::link_dirs()?;
+    dirs_with_links.push(dirs::opt_dir()?.to_owned());
+    for dir in dirs_with_links {
+        for path in dangling_links(&dir)? {
+            problems.push(Problem::DanglingLink(path));
+        }
+    }
+
+    for path in dirs::bottle_dirs()? {
+        if !bottle_paths.contains(path.as_path()) {
+            problems.push(Problem::UntrackedBottleDir(path));
+        }
+    }
+
+    let bin_dir = dirs::bin_dir()?;
+    let path_var = env::var_os("PATH").unwrap_or_default();
+    if !env::split_paths(&path_var).any(|p| p == bin_dir) {
+        problems.push(Problem::BinDirNotInPath(bin_dir.to_owned()));
+    }
+
+    Ok(problems)
+}
+
+/// Returns the symlinks in `dir` that point into the bottles dir, but whose
+/// targets no longer exist.
+fn dangling_links(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let bottles_dir = dirs::bottles_dir()?;
+
+    let mut links = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Ok(target) = fs::read_link(&path) else {
+            continue;
+        };
+        if target.starts_with(bottles_dir) && !path.exists() {
+            links.push(path);
+        }
+    }
+    links.sort();
+
+    Ok(links)
+}
+
+impl Problem {
+    /// Returns `false` if the problem has to be fixed by the user.
+    fn fix(&self) -> anyhow::Result<bool> {
+        match self {
+            Problem::StaleDependency(dependency) => {
+                dependency.delete()?;
+            }
+            Problem::ForeignLinkedFile(linked_file, _) => {
+                linked_file.delete()?;
+            }
+            Problem::MissingBottleDir(bottle) => {
+                let formula = Formula::get_exact(bottle.name())?;
+                if formula.versions.stable != bottle.version() {
+                    println!(
+                        "  {} {} is no longer available to download",
+                        bottle.name(),
+                        bottle.version(),
+                    );
+                    return Ok(false);
+                }
+
+                let progress = Progress::new();
+                let progress = progress.start(format!("{} {}", bottle.name(), bottle.version()))?;
+                formula.redownload_bottle(bottle, &progress)?;
+                progress.finish()?;
+            }
+            Problem::DanglingLink(path) => {
+                // Links into a missing bottle are valid again once it has been
+                // downloaded
+                if path.exists() {
+                    return Ok(true);
+                }
+                if let Err(err) = fs::remove_file(path)
+                    && err.kind() != io::ErrorKind::NotFound
+                {
+                    return Err(err.into());
+                }
+            }
+            // The directory may hold a bottle that the DB has lost track of,
+            // so leave it for `chug rebuild-db` rather than deleting it
+            Problem::UntrackedBottleDir(_) | Problem::BinDirNotInPath(_) => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::StaleDependency(dependency) => write!(
+                f,
+                "Dependency refers to a missing bottle (dependent: {:?}, dependency: {})",
+                dependency.dependent_id(),
+                dependency.dependency_id(),
+            ),
+            Problem::ForeignLinkedFile(linked_file, bottle) => write!(
+                f,
+                "{} is recorded as linked by {bottle}, but does not link to it",
+                linked_file.path().display(),
+            ),
+            Problem::MissingBottleDir(bottle) => write!(
+                f,
+                "Directory for {} {} is missing: {}",
+                bottle.name(),
+                bottle.version(),
+                bottle.path().display(),
+            ),
+            Problem::DanglingLink(path) => write!(f, "Dangling symlink: {}", path.display()),
+            Problem::UntrackedBottleDir(path) => {
+                write!(
+                    f,
+                    "Bottle directory is not in the DB: {}. Run `chug rebuild-db` to add it",
+                    path.display(),
+                )
+            }
+            Problem::BinDirNotInPath(bin_dir) => write!(
+                f,
+                "{} is not in $PATH. Add it in your shell's profile",
+                bin_dir.display(),
+            ),
+        }
+    }
+}
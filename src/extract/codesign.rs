@@ -0,0 +1,401 @@
+//! Ad-hoc code signing of Mach-O files
+//!
+//! Produces the same structure as `codesign --sign -`: an embedded signature containing a SHA-256
+//! code directory, an empty set of requirements and an empty CMS signature.
+//!
+//! See also: https://github.com/apple-oss-distributions/xnu/blob/main/osfmk/kern/cs_blobs.h
+
+use anyhow::Context;
+use goblin::mach::{
+    MachO,
+    constants::cputype::CPU_TYPE_ARM64,
+    header::{MH_EXECUTE, SIZEOF_HEADER_64},
+    load_command::{CommandVariant, LC_CODE_SIGNATURE, SegmentCommand64},
+};
+use ring::digest;
+
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade0cc0;
+const CSMAGIC_CODEDIRECTORY: u32 = 0xfade0c02;
+const CSMAGIC_REQUIREMENTS: u32 = 0xfade0c01;
+const CSMAGIC_BLOBWRAPPER: u32 = 0xfade0b01;
+
+const CSSLOT_CODEDIRECTORY: u32 = 0;
+const CSSLOT_REQUIREMENTS: u32 = 2;
+const CSSLOT_SIGNATURESLOT: u32 = 0x10000;
+
+const CS_ADHOC: u32 = 0x2;
+const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+const CS_HASHTYPE_SHA256: u8 = 2;
+
+/// The earliest version with the executable segment fields.
+const CODE_DIRECTORY_VERSION: u32 = 0x20400;
+const CODE_DIRECTORY_HEADER_SIZE: usize = 88;
+const HASH_SIZE: usize = 32;
+const PAGE_SIZE_LOG2: u8 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_SIZE_LOG2;
+/// Slots for the hashes of the Info.plist and the requirements.
+const SPECIAL_SLOTS: usize = 2;
+
+const LINKEDIT_DATA_COMMAND_SIZE: usize = 16;
+const SIGNATURE_ALIGNMENT: usize = 16;
+
+/// Offsets of the header fields that need to be updated.
+const NCMDS_OFFSET: usize = 16;
+const SIZEOF_CMDS_OFFSET: usize = 20;
+
+/// Offsets of the fields of `LC_CODE_SIGNATURE` and `LC_SEGMENT_64` that need to be updated.
+const DATAOFF_OFFSET: usize = 8;
+const DATASIZE_OFFSET: usize = 12;
+const VMSIZE_OFFSET: usize = 32;
+const FILESIZE_OFFSET: usize = 48;
+
+struct Layout {
+    is_executable: bool,
+    segment_alignment: u64,
+    ncmds: u32,
+    sizeofcmds: u32,
+    /// Offset of the existing `LC_CODE_SIGNATURE` command and its data.
+    code_signature: Option<(usize, usize)>,
+    /// Offset of the `__LINKEDIT` command, along with the command itself.
+    linkedit: (usize, SegmentCommand64),
+    text: SegmentCommand64,
+    /// Load commands must not grow past the first section's contents.
+    load_commands_limit: usize,
+}
+
+/// Replaces any existing signature on a thin Mach-O file with an ad-hoc one.
+pub fn sign(mut contents: Vec<u8>, identifier: &str) -> anyhow::Result<Vec<u8>> {
+    let layout = Layout::parse(&contents)?;
+
+    let (code_signature_offset, data_offset) = match layout.code_signature {
+        Some((command_offset, data_offset)) => {
+            anyhow::ensure!(
+                data_offset <= contents.len(),
+                "Code signature is outside of the file",
+            );
+            (command_offset, data_offset)
+        }
+        None => {
+            let command_offset = SIZEOF_HEADER_64 + layout.sizeofcmds as usize;
+            let command_end = command_offset + LINKEDIT_DATA_COMMAND_SIZE;
+            anyhow::ensure!(
+                command_end <= layout.load_commands_limit
+                    && contents[command_offset..command_end]
+                        .iter()
+                        .all(|&b| b == 0),
+                "Not enough space to add a code signature load command",
+            );
+
+            write_u32(&mut contents, command_offset, LC_CODE_SIGNATURE);
+            write_u32(
+                &mut contents,
+                command_offset + 4,
+                LINKEDIT_DATA_COMMAND_SIZE as u32,
+            );
+            write_u32(&mut contents, NCMDS_OFFSET, layout.ncmds + 1);
+            write_u32(
+                &mut contents,
+                SIZEOF_CMDS_OFFSET,
+                layout.sizeofcmds + LINKEDIT_DATA_COMMAND_SIZE as u32,
+            );
+
+            (
+                command_offset,
+                contents.len().next_multiple_of(SIGNATURE_ALIGNMENT),
+            )
+        }
+    };
+    contents.resize(data_offset, 0);
+
+    let (linkedit_offset, linkedit) = &layout.linkedit;
+    anyhow::ensure!(
+        linkedit.fileoff as usize <= data_offset,
+        "Code signature would be outside of the __LINKEDIT segment",
+    );
+    let code_limit = u32::try_from(data_offset).context("File is too large to sign")?;
+
+    let requirements = blob(CSMAGIC_REQUIREMENTS, &0u32.to_be_bytes());
+    let signature = blob(CSMAGIC_BLOBWRAPPER, &[]);
+    let code_slots = data_offset.div_ceil(PAGE_SIZE);
+    let ident_offset = CODE_DIRECTORY_HEADER_SIZE;
+    let hash_offset = ident_offset + identifier.len() + 1 + SPECIAL_SLOTS * HASH_SIZE;
+    let code_directory_len = hash_offset + code_slots * HASH_SIZE;
+
+    let blobs_offset = 12 + 3 * 8;
+    let signature_len = blobs_offset + code_directory_len + requirements.len() + signature.len();
+    let signature_size = signature_len.next_multiple_of(SIGNATURE_ALIGNMENT);
+
+    // The load commands are covered by the code directory, so update them before hashing
+    write_u32(
+        &mut contents,
+        code_signature_offset + DATAOFF_OFFSET,
+        code_limit,
+    );
+    write_u32(
+        &mut contents,
+        code_signature_offset + DATASIZE_OFFSET,
+        signature_size as u32,
+    );
+    let linkedit_filesize = (data_offset + signature_size) as u64 - linkedit.fileoff;
+    write_u64(
+        &mut contents,
+        linkedit_offset + FILESIZE_OFFSET,
+        linkedit_filesize,
+    );
+    write_u64(
+        &mut contents,
+        linkedit_offset + VMSIZE_OFFSET,
+        linkedit
+            .vmsize
+            .max(linkedit_filesize.next_multiple_of(layout.segment_alignment)),
+    );
+
+    let mut code_directory = Vec::with_capacity(code_directory_len);
+    for value in [
+        CSMAGIC_CODEDIRECTORY,
+        code_directory_len as u32,
+        CODE_DIRECTORY_VERSION,
+        CS_ADHOC,
+        hash_offset as u32,
+        ident_offset as u32,
+        SPECIAL_SLOTS as u32,
+        code_slots as u32,
+        code_limit,
+    ] {
+        code_directory.extend_from_slice(&value.to_be_bytes());
+    }
+    code_directory.extend_from_slice(&[HASH_SIZE as u8, CS_HASHTYPE_SHA256, 0, PAGE_SIZE_LOG2]);
+    // Unused, scatter, team and unused fields
+    code_directory.extend_from_slice(&[0; 16]);
+    // 64-bit code limit, which is only needed for files larger than 4 GiB
+    code_directory.extend_from_slice(&0u64.to_be_bytes());
+    code_directory.extend_from_slice(&layout.text.fileoff.to_be_bytes());
+    code_directory.extend_from_slice(&layout.text.filesize.to_be_bytes());
+    let exec_seg_flags = if layout.is_executable {
+        CS_EXECSEG_MAIN_BINARY
+    } else {
+        0
+    };
+    code_directory.extend_from_slice(&exec_seg_flags.to_be_bytes());
+    debug_assert_eq!(code_directory.len(), CODE_DIRECTORY_HEADER_SIZE);
+
+    code_directory.extend_from_slice(identifier.as_bytes());
+    code_directory.push(0);
+    // Special slots are stored in reverse order, so the Info.plist slot is last
+    code_directory.extend_from_slice(digest::digest(&digest::SHA256, &requirements).as_ref());
+    code_directory.extend_from_slice(&[0; HASH_SIZE]);
+    for page in contents.chunks(PAGE_SIZE) {
+        code_directory.extend_from_slice(digest::digest(&digest::SHA256, page).as_ref());
+    }
+    debug_assert_eq!(code_directory.len(), code_directory_len);
+
+    let mut offset = blobs_offset;
+    let mut super_blob = Vec::with_capacity(signature_size);
+    for value in [CSMAGIC_EMBEDDED_SIGNATURE, signature_len as u32, 3] {
+        super_blob.extend_from_slice(&value.to_be_bytes());
+    }
+    for (slot, blob) in [
+        (CSSLOT_CODEDIRECTORY, &code_directory),
+        (CSSLOT_REQUIREMENTS, &requirements),
+        (CSSLOT_SIGNATURESLOT, &signature),
+    ] {
+        super_blob.extend_from_slice(&slot.to_be_bytes());
+        super_blob.extend_from_slice(&(offset as u32).to_be_bytes());
+        offset += blob.len();
+    }
+    super_blob.extend_from_slice(&code_directory);
+    super_blob.extend_from_slice(&requirements);
+    super_blob.extend_from_slice(&signature);
+    super_blob.resize(signature_size, 0);
+
+    contents.extend_from_slice(&super_blob);
+
+    Ok(contents)
+}
+
+impl Layout {
+    fn parse(contents: &[u8]) -> anyhow::Result<Self> {
+        let macho = MachO::parse(contents, 0)?;
+        anyhow::ensure!(
+            macho.is_64 && macho.little_endian,
+            "Only 64-bit little-endian Mach-O files can be signed",
+        );
+
+        let mut code_signature = None;
+        let mut linkedit = None;
+        let mut text = None;
+        for command in &macho.load_commands {
+            match command.command {
+                CommandVariant::CodeSignature(cmd) => {
+                    code_signature = Some((command.offset, cmd.dataoff as usize));
+                }
+                CommandVariant::Segment64(cmd) if segment_name(&cmd.segname) == b"__LINKEDIT" => {
+                    linkedit = Some((command.offset, cmd));
+                }
+                CommandVariant::Segment64(cmd) if segment_name(&cmd.segname) == b"__TEXT" => {
+                    text = Some(cmd);
+                }
+                _ => {}
+            }
+        }
+
+        let mut load_commands_limit = contents.len();
+        for segment in macho.segments.iter() {
+            for (section, _) in segment.sections()? {
+                if section.offset != 0 {
+                    load_commands_limit = load_commands_limit.min(section.offset as usize);
+                }
+            }
+        }
+
+        Ok(Layout {
+            is_executable: macho.header.filetype == MH_EXECUTE,
+            segment_alignment: if macho.header.cputype == CPU_TYPE_ARM64 {
+                0x4000
+            } else {
+                0x1000
+            },
+            ncmds: macho.header.ncmds as u32,
+            sizeofcmds: macho.header.sizeofcmds,
+            code_signature,
+            linkedit: linkedit.context("Mach-O file has no __LINKEDIT segment")?,
+            text: text.context("Mach-O file has no __TEXT segment")?,
+            load_commands_limit,
+        })
+    }
+}
+
+fn segment_name(segname: &[u8; 16]) -> &[u8] {
+    let len = segname
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(segname.len());
+    &segname[..len]
+}
+
+fn blob(magic: u32, contents: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(8 + contents.len());
+    blob.extend_from_slice(&magic.to_be_bytes());
+    blob.extend_from_slice(&((8 + contents.len()) as u32).to_be_bytes());
+    blob.extend_from_slice(contents);
+    blob
+}
+
+// Mach-O headers are in the file's byte order, unlike the signature which is always big-endian
+fn write_u32(contents: &mut [u8], offset: usize, value: u32) {
+    contents[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(contents: &mut [u8], offset: usize, value: u64) {
+    contents[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use goblin::mach::{MachO, load_command::CommandVariant};
+    use ring::digest;
+
+    use super::{CSMAGIC_CODEDIRECTORY, CSMAGIC_EMBEDDED_SIGNATURE, PAGE_SIZE, sign};
+
+    const TEXT_SIZE: usize = 0x4000;
+    const LINKEDIT_SIZE: usize = 0x10;
+
+    /// Builds a minimal unsigned arm64 executable with a `__TEXT` and a `__LINKEDIT` segment.
+    pub(in crate::extract) fn unsigned_executable() -> Vec<u8> {
+        let mut contents = Vec::new();
+        for value in [0xfeedfacf, 0x0100000c, 0, 0x2, 2, 72 + 80 + 72, 0, 0] {
+            contents.extend_from_slice(&u32::to_le_bytes(value));
+        }
+
+        segment(&mut contents, b"__TEXT", 0, TEXT_SIZE, 1);
+        contents.extend_from_slice(&name(b"__text"));
+        contents.extend_from_slice(&name(b"__TEXT"));
+        for value in [0x1000u64, 0x100] {
+            contents.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0x1000u32, 2, 0, 0, 0x80000400, 0, 0, 0] {
+            contents.extend_from_slice(&value.to_le_bytes());
+        }
+
+        segment(&mut contents, b"__LINKEDIT", TEXT_SIZE, LINKEDIT_SIZE, 0);
+
+        contents.resize(0x1000, 0);
+        contents.extend((0..0x100).map(|i| i as u8));
+        contents.resize(TEXT_SIZE + LINKEDIT_SIZE, 0xaa);
+        contents
+    }
+
+    fn segment(contents: &mut Vec<u8>, segname: &[u8], offset: usize, size: usize, nsects: u32) {
+        for value in [0x19u32, 72 + 80 * nsects] {
+            contents.extend_from_slice(&value.to_le_bytes());
+        }
+        contents.extend_from_slice(&name(segname));
+        for value in [offset, 0x4000, offset, size] {
+            contents.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+        for value in [5, 5, nsects, 0] {
+            contents.extend_from_slice(&u32::to_le_bytes(value));
+        }
+    }
+
+    fn name(name: &[u8]) -> [u8; 16] {
+        let mut padded = [0; 16];
+        padded[..name.len()].copy_from_slice(name);
+        padded
+    }
+
+    fn read_u32(contents: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(contents[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn signs_unsigned_executable() {
+        let signed = sign(unsigned_executable(), "example").unwrap();
+
+        let macho = MachO::parse(&signed, 0).unwrap();
+        let code_signature = macho
+            .load_commands
+            .iter()
+            .find_map(|command| match command.command {
+                CommandVariant::CodeSignature(cmd) => Some(cmd),
+                _ => None,
+            })
+            .unwrap();
+        let data_offset = code_signature.dataoff as usize;
+        assert_eq!(data_offset, TEXT_SIZE + LINKEDIT_SIZE);
+        assert_eq!(data_offset + code_signature.datasize as usize, signed.len());
+
+        let linkedit = macho.segments.last().unwrap();
+        assert_eq!(linkedit.name().unwrap(), "__LINKEDIT");
+        assert_eq!(
+            (linkedit.fileoff + linkedit.filesize) as usize,
+            signed.len(),
+        );
+
+        let signature = &signed[data_offset..];
+        assert_eq!(read_u32(signature, 0), CSMAGIC_EMBEDDED_SIGNATURE);
+        let code_directory = &signature[read_u32(signature, 16) as usize..];
+        assert_eq!(read_u32(code_directory, 0), CSMAGIC_CODEDIRECTORY);
+
+        let ident_offset = read_u32(code_directory, 20) as usize;
+        assert_eq!(
+            &code_directory[ident_offset..ident_offset + 8],
+            b"example\0"
+        );
+
+        let hash_offset = read_u32(code_directory, 16) as usize;
+        let code_slots = read_u32(code_directory, 28) as usize;
+        assert_eq!(code_slots, data_offset.div_ceil(PAGE_SIZE));
+        for (i, page) in signed[..data_offset].chunks(PAGE_SIZE).enumerate() {
+            let hash = &code_directory[hash_offset + i * 32..hash_offset + (i + 1) * 32];
+            assert_eq!(hash, digest::digest(&digest::SHA256, page).as_ref());
+        }
+    }
+
+    #[test]
+    fn resigning_replaces_existing_signature() {
+        let signed = sign(unsigned_executable(), "example").unwrap();
+        let resigned = sign(signed.clone(), "example").unwrap();
+        assert_eq!(signed, resigned);
+    }
+}
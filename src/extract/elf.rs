@@ -0,0 +1,240 @@
+use std::{collections::HashMap, io::Write, path::Path};
+
+use goblin::elf::{
+    Elf,
+    dynamic::{DT_NEEDED, DT_RPATH, DT_RUNPATH},
+    program_header::PT_DYNAMIC,
+};
+
+use super::placeholders::{PLACEHOLDER_PREFIX, Placeholders};
+
+pub fn patch_and_write(
+    path: &Path,
+    output: &mut impl Write,
+    contents: &[u8],
+    placeholders: &Placeholders,
+) -> anyhow::Result<bool> {
+    let elf = Elf::parse(contents)?;
+
+    let relocate = |value: &str| placeholders.replace(value).into_owned();
+    let has_placeholder = |value: &&str| value.contains(PLACEHOLDER_PREFIX);
+
+    // arwen can only give every RUNPATH and RPATH entry the same value, but it can replace
+    // each DT_NEEDED entry by its own. So the entries to relocate are disguised as DT_NEEDED
+    // entries, and get their tags back once arwen has written the binary.
+    let mut runpaths = Vec::new();
+    let mut needed = HashMap::new();
+    for (index, entry) in elf
+        .dynamic
+        .iter()
+        .flat_map(|dynamic| &dynamic.dyns)
+        .enumerate()
+    {
+        if entry.d_tag != DT_RPATH && entry.d_tag != DT_RUNPATH {
+            continue;
+        }
+        let Some(runpath) = elf.dynstrtab.get_at(entry.d_val as usize) else {
+            continue;
+        };
+        if has_placeholder(&runpath) {
+            runpaths.push((index, entry.d_tag));
+            needed.insert(runpath.to_owned(), relocate(runpath));
+        }
+    }
+    needed.extend(
+        elf.libraries
+            .iter()
+            .copied()
+            .filter(has_placeholder)
+            .map(|lib| (lib.to_owned(), relocate(lib))),
+    );
+    let interpreter = elf.interpreter.filter(has_placeholder);
+
+    if needed.is_empty() && interpreter.is_none() {
+        output.write_all(contents)?;
+        return Ok(false);
+    }
+
+    let mut disguised = contents.to_vec();
+    let needed_tags = runpaths.iter().map(|&(index, _)| (index, DT_NEEDED));
+    set_dynamic_tags(&mut disguised, needed_tags)?;
+
+    // arwen rebuilds the string tables and segments, so the new paths may be longer than the
+    // placeholders they replace
+    let mut elf = arwen::elf::ElfContainer::parse(&disguised)?;
+    if !needed.is_empty() {
+        elf.replace_needed(&needed)?;
+    }
+    if let Some(interpreter) = interpreter {
+        elf.set_interpreter(&relocate(interpreter))?;
+    }
+
+    let mut patched = Vec::new();
+    elf.write(&mut patched)?;
+
+    // arwen keeps the order of the dynamic entries, but check that the disguised ones are
+    // still where they were before restoring their tags
+    let elf = Elf::parse(&patched)?;
+    let dyns = elf
+        .dynamic
+        .as_ref()
+        .map_or(&[][..], |dynamic| &dynamic.dyns);
+    for &(index, _) in &runpaths {
+        anyhow::ensure!(
+            dyns.get(index)
+                .is_some_and(|entry| entry.d_tag == DT_NEEDED),
+            "Dynamic entries of {path:?} moved while relocating them",
+        );
+    }
+    set_dynamic_tags(&mut patched, runpaths)?;
+
+    output.write_all(&patched)?;
+
+    Ok(true)
+}
+
+/// Overwrites the tags of the dynamic entries at the given indices.
+fn set_dynamic_tags(
+    contents: &mut [u8],
+    tags: impl IntoIterator<Item = (usize, u64)>,
+) -> anyhow::Result<()> {
+    let elf = Elf::parse(contents)?;
+    let Some(dynamic) = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == PT_DYNAMIC)
+    else {
+        return Ok(());
+    };
+    let offset = dynamic.p_offset as usize;
+    let (is_64, little_endian) = (elf.is_64, elf.little_endian);
+
+    let entry_size = if is_64 { 16 } else { 8 };
+    for (index, tag) in tags {
+        let start = offset + index * entry_size;
+        let bytes = match (is_64, little_endian) {
+            (true, true) => tag.to_le_bytes().to_vec(),
+            (true, false) => tag.to_be_bytes().to_vec(),
+            (false, true) => u32::try_from(tag)?.to_le_bytes().to_vec(),
+            (false, false) => u32::try_from(tag)?.to_be_bytes().to_vec(),
+        };
+        contents
+            .get_mut(start..start + bytes.len())
+            .ok_or_else(|| anyhow::anyhow!("Dynamic entry {index} is out of bounds"))?
+            .copy_from_slice(&bytes);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use goblin::elf::{
+        Elf,
+        dynamic::{DT_NEEDED, DT_RPATH},
+    };
+
+    use super::{patch_and_write, set_dynamic_tags};
+    use crate::extract::placeholders::Placeholders;
+
+    fn placeholders() -> Placeholders {
+        Placeholders::from_replacements(vec![("@@HOMEBREW_PREFIX@@", "/opt/chug".to_owned())])
+    }
+
+    /// Returns a copy of the system shell with the given RUNPATH and RPATH entries.
+    fn with_runpaths(runpath: Option<&str>, rpath: Option<&str>) -> Vec<u8> {
+        let contents = fs::read("/bin/sh").unwrap();
+        let mut elf = arwen::elf::ElfContainer::parse(&contents).unwrap();
+        if let Some(runpath) = runpath {
+            elf.set_runpath(runpath).unwrap();
+        }
+        if let Some(rpath) = rpath {
+            // arwen can't add an RPATH next to a RUNPATH, so add it as a library and retag it
+            elf.add_needed(vec![rpath.to_owned()]).unwrap();
+        }
+
+        let mut output = Vec::new();
+        elf.write(&mut output).unwrap();
+        if let Some(rpath) = rpath {
+            let elf = Elf::parse(&output).unwrap();
+            let index = elf
+                .dynamic
+                .unwrap()
+                .dyns
+                .iter()
+                .position(|entry| {
+                    entry.d_tag == DT_NEEDED
+                        && elf.dynstrtab.get_at(entry.d_val as usize) == Some(rpath)
+                })
+                .unwrap();
+            set_dynamic_tags(&mut output, [(index, DT_RPATH)]).unwrap();
+        }
+        output
+    }
+
+    fn patch(contents: &[u8]) -> (bool, Vec<u8>) {
+        let mut output = Vec::new();
+        let relocated =
+            patch_and_write(Path::new("foo"), &mut output, contents, &placeholders()).unwrap();
+        (relocated, output)
+    }
+
+    #[test]
+    fn relocates_runpath() {
+        let contents = with_runpaths(Some("@@HOMEBREW_PREFIX@@/lib:$ORIGIN"), None);
+        let (relocated, output) = patch(&contents);
+
+        let elf = Elf::parse(&output).unwrap();
+        assert!(relocated);
+        assert_eq!(elf.runpaths, ["/opt/chug/lib:$ORIGIN"]);
+        assert!(elf.rpaths.is_empty());
+    }
+
+    #[test]
+    fn relocates_rpath_without_changing_its_tag() {
+        let contents = with_runpaths(None, Some("@@HOMEBREW_PREFIX@@/lib"));
+        let (relocated, output) = patch(&contents);
+
+        let elf = Elf::parse(&output).unwrap();
+        assert!(relocated);
+        assert_eq!(elf.rpaths, ["/opt/chug/lib"]);
+        assert!(elf.runpaths.is_empty());
+    }
+
+    #[test]
+    fn relocates_runpath_and_rpath_independently() {
+        let contents = with_runpaths(
+            Some("@@HOMEBREW_PREFIX@@/lib"),
+            Some("@@HOMEBREW_PREFIX@@/opt/foo/lib:/usr/lib"),
+        );
+        let (relocated, output) = patch(&contents);
+
+        let elf = Elf::parse(&output).unwrap();
+        assert!(relocated);
+        assert_eq!(elf.runpaths, ["/opt/chug/lib"]);
+        assert_eq!(elf.rpaths, ["/opt/chug/opt/foo/lib:/usr/lib"]);
+        assert!(!elf.libraries.iter().any(|lib| lib.contains("/lib")));
+    }
+
+    #[test]
+    fn keeps_rpath_without_placeholders() {
+        let contents = with_runpaths(Some("@@HOMEBREW_PREFIX@@/lib"), Some("/usr/lib"));
+        let (relocated, output) = patch(&contents);
+
+        let elf = Elf::parse(&output).unwrap();
+        assert!(relocated);
+        assert_eq!(elf.runpaths, ["/opt/chug/lib"]);
+        assert_eq!(elf.rpaths, ["/usr/lib"]);
+    }
+
+    #[test]
+    fn copies_binaries_without_placeholders() {
+        let contents = with_runpaths(Some("/usr/lib"), None);
+        let (relocated, output) = patch(&contents);
+
+        assert!(!relocated);
+        assert_eq!(output, contents);
+    }
+}
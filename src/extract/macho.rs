@@ -1,20 +1,76 @@
-use std::{fs, path::Path, process::Command};
+use std::{borrow::Cow, io::Write, path::Path};
 
 use anyhow::Context;
+use goblin::mach::{
+    MultiArch,
+    fat::{FAT_MAGIC, FatArch, SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER},
+};
 
-use crate::dirs;
+use super::{
+    codesign,
+    magic::{self, Magic},
+    placeholders::{PLACEHOLDER_PREFIX, Placeholders},
+};
 
-use super::{HOMEBREW_CELLAR_PLACEHOLDER, HOMEBREW_PREFIX_PLACEHOLDER};
+pub fn patch_and_write(
+    path: &Path,
+    output: &mut impl Write,
+    contents: &[u8],
+    placeholders: &Placeholders,
+) -> anyhow::Result<bool> {
+    let Some(patched) = patch(contents, placeholders)? else {
+        output.write_all(contents)?;
+        return Ok(false);
+    };
 
-pub fn patch_and_write(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
-    let macho = goblin::mach::MachO::parse(contents, 0)?;
+    output.write_all(&codesign::sign(patched, identifier(path)?)?)?;
+
+    Ok(true)
+}
+
+pub fn patch_and_write_fat(
+    path: &Path,
+    output: &mut impl Write,
+    contents: &[u8],
+    placeholders: &Placeholders,
+) -> anyhow::Result<bool> {
+    let multi_arch = MultiArch::new(contents)?;
+    let arches = multi_arch.arches()?;
+
+    let identifier = identifier(path)?;
+    let mut slices = Vec::new();
+    let mut patched_any = false;
+    for arch in &arches {
+        let slice = arch.slice(contents);
+        anyhow::ensure!(!slice.is_empty(), "Invalid slice in universal binary");
 
-    let homebrew_prefix = dirs::data_dir()?
-        .to_str()
-        .context("Data dir path is non-utf8")?;
-    let homebrew_cellar = dirs::bottles_dir()?
-        .to_str()
-        .context("Bottles dir path is non-utf8")?;
+        // Universal static libraries contain archives rather than Mach-O files
+        if !matches!(magic::detect(slice), Ok(Magic::MachO)) {
+            slices.push(Cow::Borrowed(slice));
+            continue;
+        }
+
+        if let Some(patched) = patch(slice, placeholders)? {
+            slices.push(Cow::Owned(codesign::sign(patched, identifier)?));
+            patched_any = true;
+        } else {
+            slices.push(Cow::Borrowed(slice));
+        }
+    }
+
+    if !patched_any {
+        output.write_all(contents)?;
+        return Ok(false);
+    }
+
+    output.write_all(&build_fat(&arches, &slices)?)?;
+
+    Ok(true)
+}
+
+/// Returns `None` if the file does not need patching.
+fn patch(contents: &[u8], placeholders: &Placeholders) -> anyhow::Result<Option<Vec<u8>>> {
+    let macho = goblin::mach::MachO::parse(contents, 0)?;
 
     let mut replacements = Vec::new();
     for (index, lib) in macho.libs.iter().enumerate() {
@@ -23,22 +79,13 @@ pub fn patch_and_write(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
             continue;
         }
 
-        if lib.starts_with(HOMEBREW_PREFIX_PLACEHOLDER) {
-            replacements.push((
-                lib,
-                lib.replace(HOMEBREW_PREFIX_PLACEHOLDER, homebrew_prefix),
-            ));
-        } else if lib.starts_with(HOMEBREW_CELLAR_PLACEHOLDER) {
-            replacements.push((
-                lib,
-                lib.replace(HOMEBREW_CELLAR_PLACEHOLDER, homebrew_cellar),
-            ));
+        if lib.contains(PLACEHOLDER_PREFIX) {
+            replacements.push((lib, placeholders.replace(lib).into_owned()));
         }
     }
 
     if replacements.is_empty() {
-        fs::write(path, contents)?;
-        return Ok(());
+        return Ok(None);
     }
 
     let mut macho = arwen::macho::MachoContainer::parse(contents)?;
@@ -46,15 +93,213 @@ pub fn patch_and_write(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
         macho.change_install_name(old, new)?;
     }
 
-    fs::write(path, macho.data)?;
+    Ok(Some(macho.data))
+}
+
+/// The largest slice alignment accepted from a universal binary, as a power of two. `lipo` aligns
+/// slices to pages, which are at most 16KiB (2^14).
+const MAX_ALIGN: u32 = 15;
+
+/// Lays the slices out again, as patching may have changed their sizes.
+fn build_fat(arches: &[FatArch], slices: &[Cow<[u8]>]) -> anyhow::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    output.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+    output.extend_from_slice(&u32::try_from(arches.len())?.to_be_bytes());
 
-    Command::new("codesign")
-        .arg("--force")
-        .arg("--sign")
-        .arg("-")
-        .arg(path)
-        .output()
-        .context("Failed to codesign patched binary")?;
+    let mut offset = SIZEOF_FAT_HEADER + arches.len() * SIZEOF_FAT_ARCH;
+    let mut offsets = Vec::new();
+    for (arch, slice) in arches.iter().zip(slices) {
+        anyhow::ensure!(
+            arch.align <= MAX_ALIGN,
+            "Invalid alignment 2^{} in universal binary",
+            arch.align,
+        );
+        offset = offset.next_multiple_of(1 << arch.align);
+        offsets.push(offset);
 
-    Ok(())
+        output.extend_from_slice(&arch.cputype.to_be_bytes());
+        output.extend_from_slice(&arch.cpusubtype.to_be_bytes());
+        output.extend_from_slice(
+            &u32::try_from(offset)
+                .context("Universal binary is too large")?
+                .to_be_bytes(),
+        );
+        output.extend_from_slice(&u32::try_from(slice.len())?.to_be_bytes());
+        output.extend_from_slice(&arch.align.to_be_bytes());
+
+        offset += slice.len();
+    }
+
+    for (offset, slice) in offsets.into_iter().zip(slices) {
+        output.resize(offset, 0);
+        output.extend_from_slice(slice);
+    }
+
+    Ok(output)
+}
+
+/// Matches the identifier `codesign` uses for files without an Info.plist.
+fn identifier(path: &Path) -> anyhow::Result<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .context("Binary has a non-utf8 file name")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, path::PathBuf};
+
+    use goblin::mach::{MachO, MultiArch, fat::FatArch};
+
+    use super::{
+        super::codesign::tests::unsigned_executable, build_fat, patch_and_write,
+        patch_and_write_fat,
+    };
+    use crate::extract::placeholders::Placeholders;
+
+    const LC_LOAD_DYLIB: u32 = 0xc;
+    const DYLIB_COMMAND_SIZE: usize = 24;
+    const NCMDS_OFFSET: usize = 16;
+    const SIZEOF_CMDS_OFFSET: usize = 20;
+    const LOAD_COMMANDS_OFFSET: usize = 32;
+
+    fn path() -> PathBuf {
+        PathBuf::from("libfoo.dylib")
+    }
+
+    fn placeholders() -> Placeholders {
+        Placeholders::from_replacements(vec![("@@HOMEBREW_PREFIX@@", "/opt/chug".to_owned())])
+    }
+
+    /// Builds a minimal arm64 executable that links against each of `libs`.
+    fn executable(libs: &[&str]) -> Vec<u8> {
+        let mut contents = unsigned_executable();
+        let ncmds = read_u32(&contents, NCMDS_OFFSET);
+        let sizeofcmds = read_u32(&contents, SIZEOF_CMDS_OFFSET);
+
+        let mut offset = LOAD_COMMANDS_OFFSET + sizeofcmds as usize;
+        for lib in libs {
+            let size = (DYLIB_COMMAND_SIZE + lib.len() + 1).next_multiple_of(8);
+            let mut command = Vec::new();
+            for value in [
+                LC_LOAD_DYLIB,
+                size as u32,
+                DYLIB_COMMAND_SIZE as u32,
+                0,
+                0,
+                0,
+            ] {
+                command.extend_from_slice(&value.to_le_bytes());
+            }
+            command.extend_from_slice(lib.as_bytes());
+            command.resize(size, 0);
+
+            contents[offset..offset + size].copy_from_slice(&command);
+            offset += size;
+        }
+
+        write_u32(&mut contents, NCMDS_OFFSET, ncmds + libs.len() as u32);
+        write_u32(
+            &mut contents,
+            SIZEOF_CMDS_OFFSET,
+            (offset - LOAD_COMMANDS_OFFSET) as u32,
+        );
+        contents
+    }
+
+    fn read_u32(contents: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(contents[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn write_u32(contents: &mut [u8], offset: usize, value: u32) {
+        contents[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn libs(contents: &[u8]) -> Vec<String> {
+        let macho = MachO::parse(contents, 0).unwrap();
+        macho.libs.iter().map(|lib| lib.to_string()).collect()
+    }
+
+    fn fat_arch(cputype: u32, align: u32) -> FatArch {
+        FatArch {
+            cputype,
+            cpusubtype: 0,
+            offset: 0,
+            size: 0,
+            align,
+        }
+    }
+
+    #[test]
+    fn fat_binary_round_trips() {
+        let thin = executable(&["/usr/lib/libSystem.B.dylib"]);
+        let archive = b"!<arch>\n".to_vec();
+        let arches = [fat_arch(0x0100000c, 14), fat_arch(0x01000007, 12)];
+        let slices = [Cow::Borrowed(&thin[..]), Cow::Borrowed(&archive[..])];
+
+        let fat = build_fat(&arches, &slices).unwrap();
+        let multi_arch = MultiArch::new(&fat).unwrap();
+        let parsed = multi_arch.arches().unwrap();
+        assert_eq!(parsed.len(), 2);
+        for ((parsed, arch), slice) in parsed.iter().zip(&arches).zip(&slices) {
+            assert_eq!(parsed.cputype, arch.cputype);
+            assert_eq!(parsed.align, arch.align);
+            assert_eq!(parsed.offset % (1 << arch.align), 0);
+            assert_eq!(parsed.slice(&fat), &slice[..]);
+        }
+
+        // Nothing to relocate, so the file is copied as is
+        let mut output = Vec::new();
+        assert!(!patch_and_write_fat(&path(), &mut output, &fat, &placeholders()).unwrap());
+        assert_eq!(output, fat);
+    }
+
+    #[test]
+    fn rejects_invalid_fat_alignment() {
+        let thin = executable(&["/usr/lib/libSystem.B.dylib"]);
+        for align in [16, 64] {
+            let arches = [fat_arch(0x0100000c, align)];
+            assert!(build_fat(&arches, &[Cow::Borrowed(&thin[..])]).is_err());
+        }
+    }
+
+    #[test]
+    fn relocates_each_slice_of_fat_binary() {
+        let thin = executable(&[
+            "/usr/lib/libSystem.B.dylib",
+            "@@HOMEBREW_PREFIX@@/opt/foo/lib/libfoo.dylib",
+        ]);
+        let archive = b"!<arch>\n".to_vec();
+        let arches = [fat_arch(0x0100000c, 14), fat_arch(0x01000007, 12)];
+        let fat = build_fat(
+            &arches,
+            &[Cow::Borrowed(&thin[..]), Cow::Borrowed(&archive[..])],
+        )
+        .unwrap();
+
+        let mut output = Vec::new();
+        assert!(patch_and_write_fat(&path(), &mut output, &fat, &placeholders()).unwrap());
+
+        let multi_arch = MultiArch::new(&output).unwrap();
+        let parsed = multi_arch.arches().unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert!(
+            libs(parsed[0].slice(&output))
+                .contains(&"/opt/chug/opt/foo/lib/libfoo.dylib".to_owned())
+        );
+        assert_eq!(parsed[1].slice(&output), &archive[..]);
+    }
+
+    #[test]
+    fn relocates_thin_binary() {
+        let thin = executable(&[
+            "/usr/lib/libSystem.B.dylib",
+            "@@HOMEBREW_PREFIX@@/opt/foo/lib/libfoo.dylib",
+        ]);
+
+        let mut output = Vec::new();
+        assert!(patch_and_write(&path(), &mut output, &thin, &placeholders()).unwrap());
+
+        assert!(libs(&output).contains(&"/opt/chug/opt/foo/lib/libfoo.dylib".to_owned()));
+    }
 }
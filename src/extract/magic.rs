@@ -5,10 +5,14 @@ use anyhow::Context;
 pub enum Magic {
     MachO,
     FatMachO,
+    /// Universal binary with 64-bit offsets, which goblin can't parse.
+    FatMachO64,
     Elf,
     Unknown,
 }
 
+const MAX_FAT_ARCHES: u32 = 30;
+
 pub fn detect(contents: &[u8]) -> anyhow::Result<Magic> {
     let mut magic = [0u8; 4];
     magic.copy_from_slice(
@@ -18,9 +22,24 @@ pub fn detect(contents: &[u8]) -> anyhow::Result<Magic> {
     );
 
     match u32::from_be_bytes(magic) {
-        0xCAFEBABE => Ok(Magic::FatMachO),
+        // Java class files share this magic number, but their version number (>= 45) is in
+        // place of the architecture count
+        0xCAFEBABE if fat_arch_count(contents).is_some_and(|n| n <= MAX_FAT_ARCHES) => {
+            Ok(Magic::FatMachO)
+        }
+        0xCAFEBABF => Ok(Magic::FatMachO64),
         0xFEEDFACE | 0xFEEDFACF | 0xCEFAEDFE | 0xCFFAEDFE => Ok(Magic::MachO),
         0x7F454C46 => Ok(Magic::Elf),
         _ => Ok(Magic::Unknown),
     }
 }
+
+fn fat_arch_count(contents: &[u8]) -> Option<u32> {
+    let bytes = contents.get(4..8)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Treats files as binary if they contain a NUL byte, the same heuristic used by git and grep.
+pub fn is_binary(head: &[u8]) -> bool {
+    memchr::memchr(0, head).is_some()
+}
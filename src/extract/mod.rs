@@ -1,6 +1,8 @@
 use std::{
-    fs,
-    io::{self, Read},
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
     os::unix::{self, ffi::OsStrExt, fs::PermissionsExt},
     path::{Component, Path, PathBuf},
 };
@@ -8,27 +10,69 @@ use std::{
 use anyhow::Context;
 use memchr::memmem;
 
-use crate::{dirs, formulae::Formula};
+use crate::{
+    dirs,
+    formulae::Formula,
+    manifest::{self, FileKind, HashWriter, ManifestEntry},
+    receipt::InstallReceipt,
+};
 
-#[cfg(target_os = "macos")]
+#[cfg(any(target_os = "macos", test))]
+mod codesign;
+#[cfg(target_os = "linux")]
+mod elf;
+#[cfg(any(target_os = "macos", test))]
 mod macho;
 mod magic;
+mod placeholders;
 
 pub mod validate;
 
-const HOMEBREW_PREFIX_PLACEHOLDER: &str = "@@HOMEBREW_PREFIX@@";
-const HOMEBREW_CELLAR_PLACEHOLDER: &str = "@@HOMEBREW_CELLAR@@";
-const PLACEHOLDER_PREFIX: &str = "@@HOMEBREW_";
+use placeholders::{PLACEHOLDER_PREFIX, Placeholders};
+
+/// Size of the buffer used when streaming files that don't need to be parsed.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Matches the limit Linux places on symlink resolution.
+const MAX_SYMLINK_HOPS: usize = 40;
+/// Strings in binary files that are still unterminated this far past a placeholder are too long
+/// to be paths, so they aren't held back any longer. This is Linux's `PATH_MAX`.
+const MAX_PATH_LEN: usize = 4096;
 
-pub fn extract(archive: impl io::Read, formula: &Formula) -> anyhow::Result<PathBuf> {
-    let bottles_dir = dirs::bottles_dir()?;
+pub struct ExtractedBottle {
+    /// Relative to the directory the bottle was extracted into.
+    pub path: PathBuf,
+    pub receipt: Option<InstallReceipt>,
+    /// Every file in the bottle, except for its root directory.
+    pub manifest: Vec<ManifestEntry>,
+}
+
+/// Extracts the bottle into `base_dir`.
+pub fn extract(
+    archive: impl io::Read,
+    formula: &Formula,
+    base_dir: &Path,
+) -> anyhow::Result<ExtractedBottle> {
+    let placeholders = Placeholders::new(formula)?;
+    extract_inner(archive, formula, base_dir, &placeholders)
+}
 
+fn extract_inner(
+    archive: impl io::Read,
+    formula: &Formula,
+    base_dir: &Path,
+    placeholders: &Placeholders,
+) -> anyhow::Result<ExtractedBottle> {
     let mut tar = tar::Archive::new(archive);
 
     let mut bottle_path: Option<PathBuf> = None;
     // Defer directory creation
     // See also: https://github.com/alexcrichton/tar-rs/blob/5af52e0651474905f682d68c2ece702797746f80/src/archive.rs#L230
     let mut directories = Vec::new();
+    // Defer symlinks until all of them are known, so they can be validated
+    let mut symlinks = Vec::new();
+    // Defer hard links until their targets have been written (and patched)
+    let mut hard_links = Vec::new();
+    let mut entries = BTreeMap::new();
     for file in tar.entries()? {
         let file = file?;
 
@@ -62,58 +106,236 @@ pub fn extract(archive: impl io::Read, formula: &Formula) -> anyhow::Result<Path
 
         if file.header().entry_type().is_dir() {
             directories.push(file);
+        } else if file.header().entry_type().is_symlink() {
+            symlinks.push(file);
+        } else if file.header().entry_type().is_hard_link() {
+            let target = file.link_name()?.context("Hard link has no target")?;
+            anyhow::ensure!(
+                bottle_path
+                    .as_ref()
+                    .is_some_and(|prefix| target.starts_with(prefix)),
+                "Hard link points outside of bottle path: {:?}",
+                file.path()?,
+            );
+            hard_links.push(file);
         } else {
-            extract_file(file, bottles_dir)?;
+            let bottle_root = bottle_path.as_deref().context("Empty bottle")?;
+            let entry = extract_file(file, base_dir, bottle_root, placeholders)?;
+            entries.insert(entry.path.clone(), entry);
         }
     }
 
+    let bottle_root = bottle_path.as_deref().context("Empty bottle")?;
+    let symlink_targets = symlinks
+        .iter()
+        .map(|file| {
+            let target = file.link_name()?.context("Symlink has no target")?;
+            Ok((
+                file.path()?.into_owned(),
+                expand_placeholders(&target, placeholders),
+            ))
+        })
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+    let prefix = dirs::data_dir()?;
+    for (path, target) in &symlink_targets {
+        validate_symlink(path, target, bottle_root, prefix, &symlink_targets)
+            .with_context(|| format!("Refusing to extract symlink {path:?} -> {target:?}"))?;
+    }
+    for link in symlinks {
+        let entry = extract_file(link, base_dir, bottle_root, placeholders)?;
+        entries.insert(entry.path.clone(), entry);
+    }
+
+    for link in hard_links {
+        let target = link.link_name()?.context("Hard link has no target")?;
+        let target = target
+            .strip_prefix(bottle_root)
+            .ok()
+            .and_then(|target| entries.get(target))
+            .with_context(|| format!("Hard link target is not a file: {target:?}"))?;
+        let (mode, size, sha256, relocated) = (
+            target.mode,
+            target.size,
+            target.sha256.clone(),
+            target.relocated,
+        );
+
+        // Hard links share their target's contents and permissions, which may have been patched
+        let mut entry = extract_file(link, base_dir, bottle_root, placeholders)?;
+        entry.mode = mode;
+        entry.size = size;
+        entry.sha256 = sha256;
+        entry.relocated = relocated;
+        entries.insert(entry.path.clone(), entry);
+    }
+
     directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
     for dir in directories {
-        extract_file(dir, bottles_dir)?;
+        let entry = extract_file(dir, base_dir, bottle_root, placeholders)?;
+        entries.insert(entry.path.clone(), entry);
     }
 
-    let path = bottles_dir.join(bottle_path.context("Empty bottle")?);
+    let receipt = InstallReceipt::read(&base_dir.join(bottle_root))?;
 
-    Ok(path)
+    let manifest = entries
+        .into_values()
+        .filter(|entry| !entry.path.as_os_str().is_empty())
+        .collect();
+
+    Ok(ExtractedBottle {
+        path: bottle_root.to_owned(),
+        receipt,
+        manifest,
+    })
 }
 
-fn extract_file(mut file: tar::Entry<impl io::Read>, bottles_dir: &Path) -> anyhow::Result<()> {
-    let path = sanitise_path(bottles_dir, &file.path()?).context("Malformed path inside bottle")?;
+/// Returns a manifest entry whose path is relative to `bottle_root`. Hard
+/// links are recorded without a size or hash, which are left for the caller to
+/// copy from their target.
+fn extract_file(
+    mut file: tar::Entry<impl io::Read>,
+    base_dir: &Path,
+    bottle_root: &Path,
+    placeholders: &Placeholders,
+) -> anyhow::Result<ManifestEntry> {
+    let archive_path = file.path()?.into_owned();
+    let path = sanitise_path(base_dir, &archive_path).context("Malformed path inside bottle")?;
+    let relative_path = archive_path
+        .strip_prefix(bottle_root)
+        .context("Attempting to extract file outside of bottle path")?
+        .to_owned();
 
     let parent = path.parent().context("Path has no parent")?;
     fs::create_dir_all(parent)?;
 
-    let perm = fs::Permissions::from_mode(file.header().mode()?);
+    let mode = file.header().mode()?;
+    let perm = fs::Permissions::from_mode(mode);
     let kind = file.header().entry_type();
 
-    match kind {
+    let (kind, relocated, size, sha256) = match kind {
         tar::EntryType::Regular => {
-            let mut contents = Vec::new();
-            file.read_to_end(&mut contents)?;
-
-            patch_and_write(&path, &contents)?;
+            let (relocated, size, sha256) = patch_and_write(&path, &mut file, placeholders)?;
             fs::set_permissions(&path, perm)?;
+            (FileKind::File, relocated, size, Some(sha256))
         }
         tar::EntryType::Directory => {
             fs::create_dir_all(&path)?;
             fs::set_permissions(&path, perm)?;
+            (FileKind::Directory, false, 0, None)
         }
         tar::EntryType::Symlink => {
-            let target = file
-                .header()
-                .link_name()?
-                .context("Symlink has no target")?;
-            unix::fs::symlink(&target, &path)?;
+            let target = file.link_name()?.context("Symlink has no target")?;
+            let expanded = expand_placeholders(&target, placeholders);
+            unix::fs::symlink(&expanded, &path)?;
 
             // On Unix it's not possible to manipulate the permissions of a symlink
             // See also: https://github.com/rust-lang/rust/issues/75942#issuecomment-2769976820
+            let target_bytes = expanded.as_os_str().as_bytes();
+            (
+                FileKind::Symlink,
+                expanded != target.as_ref(),
+                target_bytes.len() as u64,
+                Some(manifest::hash_bytes(target_bytes)),
+            )
+        }
+        tar::EntryType::Link => {
+            let target = file.link_name()?.context("Hard link has no target")?;
+            let target = sanitise_path(base_dir, &target)
+                .context("Malformed hard link target inside bottle")?;
+            fs::hard_link(&target, &path)
+                .with_context(|| format!("Failed to create hard link {path:?}"))?;
+
+            // Hard links share their target's permissions
+            (FileKind::HardLink, false, 0, None)
         }
         _ => anyhow::bail!("Encountered unsupported tar entry type: {kind:?}"),
+    };
+
+    Ok(ManifestEntry {
+        path: relative_path,
+        kind,
+        mode,
+        size,
+        sha256,
+        relocated,
+    })
+}
+
+/// Checks that a symlink resolves to somewhere inside the bottle, or for
+/// absolute symlinks, somewhere inside the chug prefix.
+fn validate_symlink(
+    path: &Path,
+    target: &Path,
+    bottle_root: &Path,
+    prefix: &Path,
+    symlinks: &BTreeMap<PathBuf, PathBuf>,
+) -> anyhow::Result<()> {
+    if target.is_absolute() {
+        let mut normalised = PathBuf::new();
+        for component in target.components() {
+            match component {
+                Component::ParentDir => {
+                    normalised.pop();
+                }
+                Component::CurDir => {}
+                _ => normalised.push(component),
+            }
+        }
+        anyhow::ensure!(
+            normalised.starts_with(prefix),
+            "Absolute symlink target is outside of the chug prefix",
+        );
+        return Ok(());
     }
 
+    let resolved = resolve_symlink(path, symlinks);
+    anyhow::ensure!(
+        resolved.is_some_and(|r| r.starts_with(bottle_root)),
+        "Symlink target is outside of the bottle",
+    );
+
     Ok(())
 }
 
+/// Resolves a symlink within the archive, following any other symlinks along
+/// the way. Returns `None` if resolution leaves the archive or loops.
+fn resolve_symlink(path: &Path, symlinks: &BTreeMap<PathBuf, PathBuf>) -> Option<PathBuf> {
+    let mut resolved = path.parent()?.to_owned();
+    let mut pending = symlinks.get(path)?.components().rev().collect::<Vec<_>>();
+    let mut hops = 0;
+    while let Some(component) = pending.pop() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                if let Some(target) = symlinks.get(&resolved) {
+                    hops += 1;
+                    if hops > MAX_SYMLINK_HOPS || target.is_absolute() {
+                        return None;
+                    }
+                    resolved.pop();
+                    pending.extend(target.components().rev());
+                }
+            }
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(..) => return None,
+        }
+    }
+
+    Some(resolved)
+}
+
+fn expand_placeholders(path: &Path, placeholders: &Placeholders) -> PathBuf {
+    match path.to_str() {
+        Some(path_str) => placeholders.replace(path_str).into_owned().into(),
+        None => path.to_owned(),
+    }
+}
+
 fn sanitise_path(base_dir: &Path, path: &Path) -> Option<PathBuf> {
     let mut sanitised = base_dir.to_owned();
     for component in path.components() {
@@ -131,43 +353,563 @@ fn sanitise_path(base_dir: &Path, path: &Path) -> Option<PathBuf> {
     Some(sanitised)
 }
 
-fn patch_and_write(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
-    match magic::detect(contents).unwrap_or(magic::Magic::Unknown) {
+/// Returns whether any placeholders were replaced, along with the size and
+/// SHA-256 of the file that was written.
+fn patch_and_write(
+    path: &Path,
+    mut reader: impl Read,
+    placeholders: &Placeholders,
+) -> anyhow::Result<(bool, u64, String)> {
+    let mut output = HashWriter::new(BufWriter::new(File::create(path)?));
+    let mut head = Vec::with_capacity(CHUNK_SIZE);
+    (&mut reader)
+        .take(CHUNK_SIZE as u64)
+        .read_to_end(&mut head)?;
+
+    // Only executables need to be parsed as a whole, other files are streamed
+    let relocated = match magic::detect(&head).unwrap_or(magic::Magic::Unknown) {
+        #[cfg(target_os = "macos")]
+        magic::Magic::MachO => macho::patch_and_write(
+            path,
+            &mut output,
+            &read_remaining(head, reader)?,
+            placeholders,
+        )?,
         #[cfg(target_os = "macos")]
-        magic::Magic::MachO => macho::patch_and_write(path, contents)?,
+        magic::Magic::FatMachO => macho::patch_and_write_fat(
+            path,
+            &mut output,
+            &read_remaining(head, reader)?,
+            placeholders,
+        )?,
+        #[cfg(target_os = "macos")]
+        magic::Magic::FatMachO64 => {
+            anyhow::bail!("64-bit universal binaries are not supported: {path:?}")
+        }
         #[cfg(target_os = "linux")]
-        magic::Magic::Elf => todo!(),
-        _ => patch_and_write_misc(path, contents)?,
+        magic::Magic::Elf => elf::patch_and_write(
+            path,
+            &mut output,
+            &read_remaining(head, reader)?,
+            placeholders,
+        )?,
+        _ if magic::is_binary(&head) => {
+            patch_and_write_binary(path, &mut output, head, reader, placeholders)?
+        }
+        _ => patch_and_write_misc(&mut output, head, reader, placeholders)?,
+    };
+
+    let (size, sha256) = output.finish()?;
+
+    Ok((relocated, size, sha256))
+}
+
+fn read_remaining(mut head: Vec<u8>, mut reader: impl Read) -> io::Result<Vec<u8>> {
+    reader.read_to_end(&mut head)?;
+    Ok(head)
+}
+
+fn patch_and_write_misc(
+    output: &mut impl Write,
+    head: Vec<u8>,
+    mut reader: impl Read,
+    placeholders: &Placeholders,
+) -> anyhow::Result<bool> {
+    let max_placeholder_len = placeholders.max_len();
+
+    let mut relocated = false;
+    let mut buffer = head;
+    loop {
+        let at_end = (&mut reader)
+            .take(CHUNK_SIZE as u64)
+            .read_to_end(&mut buffer)?
+            == 0;
+
+        // Hold back anything that could be the start of a placeholder split across chunks
+        let limit = if at_end {
+            buffer.len()
+        } else {
+            buffer
+                .len()
+                .saturating_sub(max_placeholder_len.saturating_sub(1))
+        };
+
+        let mut last_index = 0;
+        for index in memmem::find_iter(&buffer, PLACEHOLDER_PREFIX) {
+            if index >= limit {
+                break;
+            }
+            if index < last_index {
+                continue;
+            }
+
+            let Some((placeholder, replacement)) = placeholders.find_at(&buffer[index..]) else {
+                continue;
+            };
+            output.write_all(&buffer[last_index..index])?;
+            output.write_all(replacement.as_bytes())?;
+            last_index = index + placeholder.len();
+            relocated = true;
+        }
+
+        let consumed = limit.max(last_index);
+        output.write_all(&buffer[last_index..consumed])?;
+        buffer.drain(..consumed);
+
+        if at_end {
+            break;
+        }
     }
 
-    Ok(())
+    Ok(relocated)
 }
 
-fn patch_and_write_misc(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
-    let homebrew_prefix = dirs::data_dir()?.as_os_str().as_bytes();
-    let homebrew_cellar = dirs::bottles_dir()?.as_os_str().as_bytes();
+/// Relocates the NUL-terminated strings in a binary file in place, so that the offsets of
+/// everything after them are preserved.
+fn patch_and_write_binary(
+    path: &Path,
+    output: &mut impl Write,
+    head: Vec<u8>,
+    mut reader: impl Read,
+    placeholders: &Placeholders,
+) -> anyhow::Result<bool> {
+    let max_string_len = placeholders.max_len() + MAX_PATH_LEN;
+
+    let mut relocated_any = false;
+    let mut in_long_string = false;
+    let mut buffer = head;
+    loop {
+        let at_end = (&mut reader)
+            .take(CHUNK_SIZE as u64)
+            .read_to_end(&mut buffer)?
+            == 0;
+
+        // Hold back anything that could be the start of a placeholder split across chunks
+        let limit = if at_end {
+            buffer.len()
+        } else {
+            buffer.len().saturating_sub(PLACEHOLDER_PREFIX.len() - 1)
+        };
+
+        let mut index = 0;
+        if in_long_string {
+            match memchr::memchr(0, &buffer) {
+                Some(len) => {
+                    index = len;
+                    in_long_string = false;
+                }
+                None => index = buffer.len(),
+            }
+        }
+
+        let mut consumed = None;
+        while let Some(offset) = memmem::find(&buffer[index..], PLACEHOLDER_PREFIX.as_bytes()) {
+            let start = index + offset;
+            if start >= limit {
+                break;
+            }
+            let end = match memchr::memchr(0, &buffer[start..]) {
+                Some(len) => start + len,
+                None if at_end => buffer.len(),
+                // It isn't a path, so leave the rest of it as it is
+                None if buffer.len() - start > max_string_len => {
+                    in_long_string = true;
+                    index = buffer.len();
+                    break;
+                }
+                // The string continues into the next chunk, so hold it back until it can be
+                // checked as a whole
+                None => {
+                    consumed = Some(start);
+                    break;
+                }
+            };
 
-    let mut output = Vec::new();
-    let mut last_index = 0;
-    for index in memmem::find_iter(contents, PLACEHOLDER_PREFIX) {
-        if contents[index..].starts_with(HOMEBREW_CELLAR_PLACEHOLDER.as_bytes()) {
-            output.extend_from_slice(&contents[last_index..index]);
-            output.extend_from_slice(homebrew_cellar);
-            last_index = index + HOMEBREW_CELLAR_PLACEHOLDER.len();
-        } else if contents[index..].starts_with(HOMEBREW_PREFIX_PLACEHOLDER.as_bytes()) {
-            output.extend_from_slice(&contents[last_index..index]);
-            output.extend_from_slice(homebrew_prefix);
-            last_index = index + HOMEBREW_PREFIX_PLACEHOLDER.len();
+            let original = &buffer[start..end];
+            if let Cow::Owned(relocated) = placeholders.replace_bytes(original) {
+                anyhow::ensure!(
+                    relocated.len() <= original.len(),
+                    "Relocated path {:?} is too long to replace {:?} in binary file {path:?}",
+                    String::from_utf8_lossy(&relocated),
+                    String::from_utf8_lossy(original),
+                );
+
+                buffer[start..start + relocated.len()].copy_from_slice(&relocated);
+                buffer[start + relocated.len()..end].fill(0);
+                relocated_any = true;
+            }
+
+            index = end;
+        }
+
+        let consumed = consumed.unwrap_or(limit.max(index));
+        output.write_all(&buffer[..consumed])?;
+        buffer.drain(..consumed);
+
+        if at_end {
+            break;
         }
     }
 
-    if output.is_empty() {
-        // Output will be empty if no occurrences of the placeholder were found
-        fs::write(path, contents)?;
-    } else {
-        output.extend_from_slice(&contents[last_index..]);
-        fs::write(path, output)?;
+    Ok(relocated_any)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fs,
+        os::unix::fs::{MetadataExt, PermissionsExt},
+        path::{Path, PathBuf},
+    };
+
+    use super::{
+        CHUNK_SIZE, ExtractedBottle, MAX_SYMLINK_HOPS, extract_inner, patch_and_write,
+        placeholders::Placeholders, resolve_symlink, validate_symlink,
+    };
+    use crate::{
+        formulae::Formula,
+        manifest::{self, FileKind},
+        testing::{self, TempDir},
+    };
+
+    fn placeholders() -> Placeholders {
+        Placeholders::from_replacements(vec![
+            ("@@HOMEBREW_PREFIX@@", "/opt/chug".to_owned()),
+            ("@@HOMEBREW_CELLAR@@", "/opt/chug/bottles".to_owned()),
+        ])
     }
 
-    Ok(())
+    fn formula() -> Formula {
+        serde_json::from_str(
+            r#"{
+                "name": "foo",
+                "aliases": [],
+                "dependencies": [],
+                "versions": {"stable": "1.0", "bottle": true},
+                "bottle": {"stable": {"files": {}}}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    enum Entry<'a> {
+        Directory(&'a str),
+        File(&'a str, u32, &'a str),
+        HardLink(&'a str, &'a str),
+    }
+
+    fn archive(entries: &[Entry]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for entry in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(0);
+            match *entry {
+                Entry::Directory(path) => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(0o755);
+                    builder.append_data(&mut header, path, &[][..]).unwrap();
+                }
+                Entry::File(path, mode, contents) => {
+                    header.set_mode(mode);
+                    header.set_size(contents.len() as u64);
+                    builder
+                        .append_data(&mut header, path, contents.as_bytes())
+                        .unwrap();
+                }
+                Entry::HardLink(path, target) => {
+                    header.set_entry_type(tar::EntryType::Link);
+                    // Some archivers don't record the mode of hard links
+                    header.set_mode(0);
+                    builder.append_link(&mut header, path, target).unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Extracts `entries` into a new directory, which is removed afterwards.
+    fn extract(
+        name: &str,
+        entries: &[Entry],
+        check: impl FnOnce(&Path, anyhow::Result<ExtractedBottle>),
+    ) {
+        testing::home();
+        let base_dir = TempDir::new(name);
+        let result = extract_inner(
+            &archive(entries)[..],
+            &formula(),
+            base_dir.path(),
+            &placeholders(),
+        );
+        check(base_dir.path(), result);
+    }
+
+    fn check_hard_link(base_dir: &Path, bottle: ExtractedBottle) {
+        let file = base_dir.join("foo/1.0/bin/foo");
+        let link = base_dir.join("foo/1.0/bin/foo-link");
+        assert_eq!(fs::read(&link).unwrap(), b"#!/opt/chug/bin/sh\n");
+        assert_eq!(
+            fs::metadata(&link).unwrap().ino(),
+            fs::metadata(&file).unwrap().ino(),
+        );
+        assert_eq!(
+            fs::metadata(&link).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+
+        let entry = bottle
+            .manifest
+            .iter()
+            .find(|e| e.path == Path::new("bin/foo-link"))
+            .unwrap();
+        assert_eq!(entry.kind, FileKind::HardLink);
+        assert_eq!(entry.mode, 0o755);
+        assert_eq!(entry.size, 19);
+        assert_eq!(
+            entry.sha256.as_deref(),
+            Some(manifest::hash_bytes(b"#!/opt/chug/bin/sh\n").as_str()),
+        );
+        assert!(entry.relocated);
+    }
+
+    #[test]
+    fn extracts_hard_link_inside_bottle() {
+        let entries = [
+            Entry::Directory("foo/1.0/"),
+            Entry::File("foo/1.0/bin/foo", 0o755, "#!@@HOMEBREW_PREFIX@@/bin/sh\n"),
+            Entry::HardLink("foo/1.0/bin/foo-link", "foo/1.0/bin/foo"),
+        ];
+        extract("hard-link", &entries, |base_dir, result| {
+            check_hard_link(base_dir, result.unwrap());
+        });
+    }
+
+    #[test]
+    fn extracts_hard_link_before_its_target() {
+        let entries = [
+            Entry::Directory("foo/1.0/"),
+            Entry::HardLink("foo/1.0/bin/foo-link", "foo/1.0/bin/foo"),
+            Entry::File("foo/1.0/bin/foo", 0o755, "#!@@HOMEBREW_PREFIX@@/bin/sh\n"),
+        ];
+        extract("hard-link-later", &entries, |base_dir, result| {
+            check_hard_link(base_dir, result.unwrap());
+        });
+    }
+
+    #[test]
+    fn rejects_hard_link_outside_bottle() {
+        let entries = [
+            Entry::Directory("foo/1.0/"),
+            Entry::HardLink("foo/1.0/bin/foo-link", "bar/1.0/bin/bar"),
+        ];
+        extract("hard-link-escape", &entries, |base_dir, result| {
+            let err = result.err().unwrap();
+            assert!(err.to_string().contains("outside of bottle path"), "{err}");
+            assert!(!base_dir.join("foo/1.0/bin/foo-link").exists());
+        });
+    }
+
+    fn symlinks(links: &[(&str, &str)]) -> BTreeMap<PathBuf, PathBuf> {
+        links
+            .iter()
+            .map(|(path, target)| (path.into(), target.into()))
+            .collect()
+    }
+
+    /// Validates the first of `links`, which may refer to the others.
+    fn validate(links: &[(&str, &str)]) -> anyhow::Result<()> {
+        let symlinks = symlinks(links);
+        let (path, target) = links[0];
+        validate_symlink(
+            Path::new(path),
+            Path::new(target),
+            Path::new("foo/1.0"),
+            Path::new("/opt/chug"),
+            &symlinks,
+        )
+    }
+
+    #[test]
+    fn accepts_symlinks_inside_bottle() {
+        assert!(validate(&[("foo/1.0/lib/libfoo.so", "libfoo.so.1")]).is_ok());
+        assert!(validate(&[("foo/1.0/bin/foo", "../libexec/./bin/foo")]).is_ok());
+    }
+
+    #[test]
+    fn rejects_symlinks_escaping_bottle() {
+        assert!(validate(&[("foo/1.0/bin/foo", "../../../bar/1.0/bin/bar")]).is_err());
+        assert!(validate(&[("foo/1.0/bin/foo", "../../../../../etc/passwd")]).is_err());
+        assert!(validate(&[("foo/1.0/bin/foo", "../..")]).is_err());
+    }
+
+    #[test]
+    fn checks_absolute_symlinks_against_prefix() {
+        assert!(validate(&[("foo/1.0/lib/libbar.so", "/opt/chug/opt/bar/lib/libbar.so")]).is_ok());
+        assert!(validate(&[("foo/1.0/bin/sh", "/usr/bin/sh")]).is_err());
+        assert!(validate(&[("foo/1.0/etc/passwd", "/opt/chug/../../etc/passwd")]).is_err());
+    }
+
+    #[test]
+    fn follows_symlink_chains() {
+        let links = [
+            ("foo/1.0/lib/a", "b"),
+            ("foo/1.0/lib/b", "../share/c"),
+            ("foo/1.0/share", "data"),
+        ];
+        assert_eq!(
+            resolve_symlink(Path::new(links[0].0), &symlinks(&links)).unwrap(),
+            Path::new("foo/1.0/data/c"),
+        );
+        assert!(validate(&links).is_ok());
+
+        // Each link stays inside the bottle, but the chain as a whole does not
+        let links = [
+            ("foo/1.0/lib/a", "up/bar/1.0/lib"),
+            ("foo/1.0/lib/up", "../.."),
+        ];
+        assert!(validate(&links).is_err());
+
+        // Symlinks in a chain can't be absolute, as they would escape the archive
+        let links = [("foo/1.0/lib/a", "b"), ("foo/1.0/lib/b", "/opt/chug/lib")];
+        assert!(validate(&links).is_err());
+    }
+
+    #[test]
+    fn limits_symlink_hops() {
+        let chain = |len: usize| {
+            let links = (0..len)
+                .map(|i| (format!("foo/1.0/{i}"), (i + 1).to_string()))
+                .collect::<Vec<_>>();
+            let links = links
+                .iter()
+                .map(|(path, target)| (path.as_str(), target.as_str()))
+                .collect::<Vec<_>>();
+            validate(&links)
+        };
+        assert!(chain(MAX_SYMLINK_HOPS + 1).is_ok());
+        assert!(chain(MAX_SYMLINK_HOPS + 2).is_err());
+
+        let links = [("foo/1.0/a", "b"), ("foo/1.0/b", "a")];
+        assert!(validate(&links).is_err());
+    }
+
+    fn patch(name: &str, contents: &[u8]) -> (bool, Vec<u8>) {
+        let dir = TempDir::new(name);
+        let path = dir.path().join("output");
+        let (relocated, size, sha256) = patch_and_write(&path, contents, &placeholders()).unwrap();
+        let output = fs::read(&path).unwrap();
+
+        assert_eq!(size, output.len() as u64);
+        assert_eq!(sha256, manifest::hash_bytes(&output));
+        (relocated, output)
+    }
+
+    #[test]
+    fn relocates_small_text_file() {
+        let (relocated, output) = patch("small", b"#!@@HOMEBREW_PREFIX@@/bin/perl\n");
+
+        assert!(relocated);
+        assert_eq!(output, b"#!/opt/chug/bin/perl\n");
+    }
+
+    #[test]
+    fn relocates_text_placeholder_across_chunks() {
+        for offset in 1..20 {
+            let mut contents = vec![b'a'; CHUNK_SIZE - offset];
+            contents.extend_from_slice(b"@@HOMEBREW_CELLAR@@/foo\n");
+            contents.resize(2 * CHUNK_SIZE - offset, b'b');
+            contents.extend_from_slice(b"@@HOMEBREW_PREFIX@@/bar\n");
+            contents.resize(4 * CHUNK_SIZE, b'c');
+
+            let (relocated, output) = patch("text-chunks", &contents);
+
+            let expected = String::from_utf8(contents)
+                .unwrap()
+                .replace("@@HOMEBREW_CELLAR@@", "/opt/chug/bottles")
+                .replace("@@HOMEBREW_PREFIX@@", "/opt/chug");
+            assert!(relocated);
+            assert_eq!(output, expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn relocates_binary_placeholder_across_chunks() {
+        for offset in 1..40 {
+            let mut contents = vec![0; CHUNK_SIZE - offset];
+            contents.extend_from_slice(b"@@HOMEBREW_CELLAR@@/foo/1.0/lib\0");
+            contents.resize(2 * CHUNK_SIZE - offset, 1);
+            contents.extend_from_slice(b"/usr/lib:@@HOMEBREW_PREFIX@@/lib\0");
+            contents.resize(4 * CHUNK_SIZE, 2);
+
+            let (relocated, output) = patch("binary-chunks", &contents);
+
+            let mut expected = vec![0; CHUNK_SIZE - offset];
+            expected.extend_from_slice(b"/opt/chug/bottles/foo/1.0/lib\0\0\0");
+            expected.resize(2 * CHUNK_SIZE - offset, 1);
+            expected.extend_from_slice(b"/usr/lib:/opt/chug/lib\0");
+            expected.resize(expected.len() + 10, 0);
+            expected.resize(4 * CHUNK_SIZE, 2);
+            assert!(relocated);
+            assert_eq!(output.len(), contents.len());
+            assert!(output == expected, "offset {offset}");
+        }
+    }
+
+    #[test]
+    fn copies_unterminated_binary_string_without_holding_it_back() {
+        let mut contents = vec![0; 10];
+        contents.extend_from_slice(b"@@HOMEBREW_PREFIX@@/bin");
+        contents.resize(3 * CHUNK_SIZE, 1);
+        contents.extend_from_slice(b"@@HOMEBREW_PREFIX@@/lib\0");
+        contents.push(0);
+        contents.extend_from_slice(b"@@HOMEBREW_PREFIX@@/lib\0");
+
+        let (relocated, output) = patch("binary-long-string", &contents);
+
+        let mut expected = contents.clone();
+        let end = expected.len();
+        expected[end - 24..].copy_from_slice(b"/opt/chug/lib\0\0\0\0\0\0\0\0\0\0\0");
+        assert!(relocated);
+        assert!(output == expected);
+    }
+
+    #[test]
+    fn copies_text_file_without_any_placeholders_to_replace() {
+        let contents = b"@@HOMEBREW_PREFIX@@/bin\n".repeat(CHUNK_SIZE / 8);
+        let dir = TempDir::new("no-placeholders");
+        let path = dir.path().join("output");
+        let result = patch_and_write(
+            &path,
+            &contents[..],
+            &Placeholders::from_replacements(Vec::new()),
+        );
+        let output = fs::read(&path).unwrap();
+
+        assert!(!result.unwrap().0);
+        assert_eq!(output, contents);
+    }
+
+    #[test]
+    fn rejects_binary_placeholder_with_longer_replacement() {
+        let placeholders = Placeholders::from_replacements(vec![(
+            "@@HOMEBREW_PREFIX@@",
+            "/a/much/longer/prefix".to_owned(),
+        )]);
+        let mut contents = vec![0; CHUNK_SIZE - 4];
+        contents.extend_from_slice(b"@@HOMEBREW_PREFIX@@/bin\0");
+
+        let dir = TempDir::new("binary-too-long");
+        let result = patch_and_write(&dir.path().join("output"), &contents[..], &placeholders);
+        assert!(result.unwrap_err().to_string().contains("too long"));
+    }
+
+    #[test]
+    fn copies_text_file_without_placeholders() {
+        let contents = "@@HOMEBREW_".repeat(CHUNK_SIZE / 4);
+        let (relocated, output) = patch("text-unchanged", contents.as_bytes());
+
+        assert!(!relocated);
+        assert_eq!(output, contents.as_bytes());
+    }
 }
@@ -0,0 +1,226 @@
+//! Homebrew placeholders and the values chug relocates them to
+
+use std::borrow::Cow;
+
+use anyhow::Context;
+use memchr::memmem;
+
+use crate::{dirs, formulae::Formula};
+
+pub const PLACEHOLDER_PREFIX: &str = "@@HOMEBREW_";
+
+const HOMEBREW_PREFIX_PLACEHOLDER: &str = "@@HOMEBREW_PREFIX@@";
+const HOMEBREW_CELLAR_PLACEHOLDER: &str = "@@HOMEBREW_CELLAR@@";
+const HOMEBREW_REPOSITORY_PLACEHOLDER: &str = "@@HOMEBREW_REPOSITORY@@";
+const HOMEBREW_LIBRARY_PLACEHOLDER: &str = "@@HOMEBREW_LIBRARY@@";
+const HOMEBREW_PERL_PLACEHOLDER: &str = "@@HOMEBREW_PERL@@";
+const HOMEBREW_JAVA_PLACEHOLDER: &str = "@@HOMEBREW_JAVA@@";
+/// Linux bottles use this as their ELF interpreter.
+#[cfg(target_os = "linux")]
+const HOMEBREW_LOADER_PLACEHOLDER: &str = "@@HOMEBREW_PREFIX@@/lib/ld.so";
+
+const SYSTEM_PERL: &str = "/usr/bin/perl";
+
+#[derive(Debug)]
+pub struct Placeholders {
+    /// Sorted longest first, so that placeholders which extend others take
+    /// precedence.
+    replacements: Vec<(&'static str, String)>,
+}
+
+impl Placeholders {
+    pub fn new(formula: &Formula) -> anyhow::Result<Self> {
+        let data_dir = dirs::data_dir()?;
+        let data_dir_str = data_dir.to_str().context("Data dir path is non-utf8")?;
+        let bottles_dir_str = dirs::bottles_dir()?
+            .to_str()
+            .context("Bottles dir path is non-utf8")?;
+        let opt_dir_str = dirs::opt_dir()?
+            .to_str()
+            .context("Opt dir path is non-utf8")?;
+
+        // chug has no repository of its own, so treat it like Homebrew does
+        // when the repository and prefix are the same
+        let library = format!("{data_dir_str}/Library");
+
+        let perl = if formula.dependencies.iter().any(|d| d == "perl") {
+            format!("{opt_dir_str}/perl/bin/perl")
+        } else {
+            SYSTEM_PERL.to_owned()
+        };
+
+        let openjdk = formula
+            .dependencies
+            .iter()
+            .find(|d| d.starts_with("openjdk"))
+            .map_or("openjdk", String::as_str);
+        let java = if cfg!(target_os = "macos") {
+            format!("{opt_dir_str}/{openjdk}/libexec/openjdk.jdk/Contents/Home")
+        } else {
+            format!("{opt_dir_str}/{openjdk}/libexec")
+        };
+
+        let mut replacements = vec![
+            (HOMEBREW_PREFIX_PLACEHOLDER, data_dir_str.to_owned()),
+            (HOMEBREW_CELLAR_PLACEHOLDER, bottles_dir_str.to_owned()),
+            (HOMEBREW_REPOSITORY_PLACEHOLDER, data_dir_str.to_owned()),
+            (HOMEBREW_LIBRARY_PLACEHOLDER, library),
+            (HOMEBREW_PERL_PLACEHOLDER, perl),
+            (HOMEBREW_JAVA_PLACEHOLDER, java),
+        ];
+        #[cfg(target_os = "linux")]
+        replacements.push((HOMEBREW_LOADER_PLACEHOLDER, system_loader()?.to_owned()));
+
+        Ok(Self::from_replacements(replacements))
+    }
+
+    pub fn from_replacements(mut replacements: Vec<(&'static str, String)>) -> Self {
+        replacements.sort_by_key(|(placeholder, _)| usize::MAX - placeholder.len());
+
+        Placeholders { replacements }
+    }
+
+    /// Returns the placeholder that `bytes` starts with, along with its
+    /// replacement.
+    pub fn find_at(&self, bytes: &[u8]) -> Option<(&'static str, &str)> {
+        self.replacements
+            .iter()
+            .find(|(placeholder, _)| bytes.starts_with(placeholder.as_bytes()))
+            .map(|(placeholder, replacement)| (*placeholder, replacement.as_str()))
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.replacements
+            .iter()
+            .map(|(placeholder, _)| placeholder.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn replace<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        match self.replace_bytes(value.as_bytes()) {
+            Cow::Borrowed(_) => Cow::Borrowed(value),
+            // Placeholders are ASCII, so replacing them can't split a character
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8(bytes).unwrap()),
+        }
+    }
+
+    pub fn replace_bytes<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut output = Vec::new();
+        let mut last_index = 0;
+        for index in memmem::find_iter(value, PLACEHOLDER_PREFIX) {
+            if index < last_index {
+                continue;
+            }
+            let Some((placeholder, replacement)) = self.find_at(&value[index..]) else {
+                continue;
+            };
+
+            output.extend_from_slice(&value[last_index..index]);
+            output.extend_from_slice(replacement.as_bytes());
+            last_index = index + placeholder.len();
+        }
+
+        if last_index == 0 {
+            Cow::Borrowed(value)
+        } else {
+            output.extend_from_slice(&value[last_index..]);
+            Cow::Owned(output)
+        }
+    }
+}
+
+/// Uses the same dynamic loader as the system shell.
+#[cfg(target_os = "linux")]
+fn system_loader() -> anyhow::Result<&'static str> {
+    let loader = cache!(String).get_or_init(|| {
+        let contents = std::fs::read("/bin/sh").context("Failed to read /bin/sh")?;
+        let elf = goblin::elf::Elf::parse(&contents)?;
+        let interpreter = elf
+            .interpreter
+            .context("Could not determine the system's dynamic loader")?;
+        Ok(interpreter.to_owned())
+    })?;
+    Ok(loader)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::Placeholders;
+
+    fn placeholders() -> Placeholders {
+        Placeholders::from_replacements(vec![
+            ("@@HOMEBREW_PREFIX@@", "/opt/chug".to_owned()),
+            ("@@HOMEBREW_CELLAR@@", "/opt/chug/bottles".to_owned()),
+            (
+                "@@HOMEBREW_PREFIX@@/lib/ld.so",
+                "/lib64/ld-linux-x86-64.so.2".to_owned(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn replaces_each_placeholder() {
+        let placeholders = placeholders();
+        assert_eq!(
+            placeholders.replace("@@HOMEBREW_CELLAR@@/foo/1.0:@@HOMEBREW_PREFIX@@/bin"),
+            "/opt/chug/bottles/foo/1.0:/opt/chug/bin",
+        );
+        assert_eq!(
+            placeholders.replace("@@HOMEBREW_PREFIX@@@@HOMEBREW_PREFIX@@"),
+            "/opt/chug/opt/chug",
+        );
+    }
+
+    #[test]
+    fn borrows_values_without_placeholders() {
+        let placeholders = placeholders();
+        assert!(matches!(placeholders.replace("/usr/bin"), Cow::Borrowed(_)));
+        // Only known placeholders are replaced
+        assert!(matches!(
+            placeholders.replace("@@HOMEBREW_UNKNOWN@@"),
+            Cow::Borrowed(_),
+        ));
+    }
+
+    #[test]
+    fn replaces_placeholders_longer_than_prefix() {
+        let placeholders = placeholders();
+        assert_eq!(
+            placeholders.replace("@@HOMEBREW_PREFIX@@/lib/ld.so"),
+            "/lib64/ld-linux-x86-64.so.2",
+        );
+        assert_eq!(
+            placeholders.replace("@@HOMEBREW_PREFIX@@/lib/libc.so"),
+            "/opt/chug/lib/libc.so",
+        );
+    }
+
+    #[test]
+    fn skips_overlapping_placeholders() {
+        let placeholders = placeholders();
+        // The prefix of a placeholder starts inside the unknown one, but only
+        // whole placeholders are replaced
+        assert_eq!(
+            placeholders.replace("@@HOMEBREW_@@HOMEBREW_PREFIX@@"),
+            "@@HOMEBREW_/opt/chug",
+        );
+        assert_eq!(
+            placeholders.replace("@@HOMEBREW_PREFIX@@HOMEBREW_CELLAR@@"),
+            "/opt/chugHOMEBREW_CELLAR@@",
+        );
+    }
+
+    #[test]
+    fn leaves_truncated_placeholders() {
+        let placeholders = placeholders();
+        // Truncated placeholders are left alone
+        assert_eq!(placeholders.replace("@@HOMEBREW_PREF"), "@@HOMEBREW_PREF");
+        assert_eq!(
+            placeholders.replace_bytes(b"\0@@HOMEBREW_CELLAR@@\0"),
+            &b"\0/opt/chug/bottles\0"[..],
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::{BTreeMap, btree_map::Entry},
+    fmt,
     io::Read,
 };
 
@@ -13,9 +14,16 @@ const FORMULA_API: &str = "https://formulae.brew.sh/api/formula.json";
 pub struct Formula {
     pub name: String,
     pub aliases: Vec<String>,
+    pub desc: Option<String>,
+    pub homepage: Option<String>,
+    pub license: Option<String>,
     pub dependencies: Vec<String>,
     pub versions: Versions,
     pub bottle: Bottles,
+    #[serde(default)]
+    pub keg_only: bool,
+    pub keg_only_reason: Option<KegOnlyReason>,
+    pub caveats: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,6 +32,13 @@ pub struct Versions {
     pub bottle: bool,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct KegOnlyReason {
+    pub reason: String,
+    #[serde(default)]
+    pub explanation: String,
+}
+
 impl Formula {
     pub fn all() -> anyhow::Result<&'static [Formula]> {
         let formulae = cache!(Vec<Formula>)
@@ -74,6 +89,19 @@ impl Formula {
         Ok(aliases[index].1)
     }
 
+    /// Returns `None` if the formula should be linked as usual.
+    pub fn keg_only_reason(&self) -> Option<String> {
+        if !self.keg_only {
+            return None;
+        }
+
+        let reason = self
+            .keg_only_reason
+            .as_ref()
+            .map_or_else(|| "it was marked as keg-only".to_owned(), |r| r.to_string());
+        Some(reason)
+    }
+
     pub fn resolve_dependencies(
         roots: Vec<&str>,
     ) -> anyhow::Result<BTreeMap<&'static str, &'static Formula>> {
@@ -96,3 +124,24 @@ impl Formula {
         Ok(result)
     }
 }
+
+impl fmt::Display for KegOnlyReason {
+    /// Matches the wording used by Homebrew.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.explanation.is_empty() {
+            return f.write_str(&self.explanation);
+        }
+
+        let reason = match self.reason.trim_start_matches(':') {
+            "versioned_formula" => "this is an alternate version of another formula",
+            "provided_by_macos" => {
+                "macOS already provides this software and installing another version in parallel can cause all kinds of trouble"
+            }
+            "shadowed_by_macos" => {
+                "macOS provides similar software and installing this software in parallel can cause all kinds of trouble"
+            }
+            reason => reason,
+        };
+        f.write_str(reason)
+    }
+}
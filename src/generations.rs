@@ -0,0 +1,222 @@
+//! Keeps previous sets of installed bottles around for `chug rollback`
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    os::unix,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{
+    db::models::{DownloadedBottle, Generation, Journal},
+    dirs,
+};
+
+const KEEP_GENERATIONS_VAR: &str = "CHUG_KEEP_GENERATIONS";
+const DEFAULT_KEEP_GENERATIONS: usize = 2;
+
+/// Records the installed bottles and their links as a new generation, then
+/// prunes old generations.
+pub fn record() -> anyhow::Result<()> {
+    Generation::create(&current_links()?)?;
+
+    prune(keep_generations()?)
+}
+
+/// Returns a generation that matches the installed bottles and their links,
+/// recording a new one if they have changed since the latest generation, e.g.
+/// due to `chug unlink`.
+pub fn checkpoint() -> anyhow::Result<Generation> {
+    let links = current_links()?;
+    if let Some(latest) = Generation::get_all()?.pop() {
+        let bottle_ids = DownloadedBottle::get_installed()?
+            .iter()
+            .map(|b| b.id())
+            .collect::<BTreeSet<_>>();
+        let latest_links = latest
+            .links()?
+            .iter()
+            .map(|l| (l.path().to_owned(), l.target().to_owned()))
+            .collect::<BTreeMap<_, _>>();
+        if latest_links == links
+            && latest.bottle_ids()?.into_iter().collect::<BTreeSet<_>>() == bottle_ids
+        {
+            return Ok(latest);
+        }
+    }
+
+    let generation = Generation::create(&links)?;
+    prune(keep_generations()?)?;
+
+    Ok(generation)
+}
+
+/// Switches back to the previous generation and forgets the current one.
+pub fn rollback() -> anyhow::Result<()> {
+    // Links may have changed since the latest generation, e.g. due to `chug
+    // unlink`, so that change is the one to roll back
+    checkpoint()?;
+
+    let generations = Generation::get_all()?;
+    let [.., previous, _current] = generations.as_slice() else {
+        anyhow::bail!("No previous generation to roll back to");
+    };
+
+    println!(
+        "Rolling back to generation {} from {}...",
+        previous.id(),
+        previous.created_at(),
+    );
+
+    let journal = Journal::begin(previous)?;
+    restore(previous)?;
+    journal.delete()
+}
+
+/// Makes `generation` current again, and forgets any newer generations. Each
+/// link is swapped in place, so none are ever missing.
+pub fn restore(generation: &Generation) -> anyhow::Result<()> {
+    let bottles = DownloadedBottle::get_all()?
+        .into_iter()
+        .map(|b| (b.id(), b))
+        .collect::<BTreeMap<_, _>>();
+    let generation_bottles = generation
+        .bottle_ids()?
+        .iter()
+        .filter_map(|id| bottles.get(id))
+        .collect::<Vec<_>>();
+    for bottle in &generation_bottles {
+        anyhow::ensure!(
+            bottle.path().is_dir(),
+            "Directory for {} {} is missing: {}",
+            bottle.name(),
+            bottle.version(),
+            bottle.path().display(),
+        );
+    }
+
+    let opt_dir = dirs::opt_dir()?;
+    let links = generation.links()?;
+    for link in &links {
+        replace_link(link.target(), link.path())?;
+    }
+    for bottle in &generation_bottles {
+        replace_link(bottle.path(), &opt_dir.join(bottle.name()))?;
+    }
+
+    // Remove links that the generation didn't have, including those of
+    // bottles that were only part way through being added
+    let link_paths = links.iter().map(|l| l.path()).collect::<BTreeSet<_>>();
+    let names = generation_bottles
+        .iter()
+        .map(|b| b.name())
+        .collect::<BTreeSet<_>>();
+    for bottle in bottles.values() {
+        for linked_file in bottle.linked_files()? {
+            if !link_paths.contains(linked_file.path()) {
+                remove_link(linked_file.path())?;
+            }
+        }
+        if !names.contains(bottle.name()) {
+            remove_link(&opt_dir.join(bottle.name()))?;
+        }
+    }
+
+    generation.restore()?;
+    for newer in Generation::get_all()? {
+        if newer.id() > generation.id() {
+            newer.delete()?;
+        }
+    }
+
+    prune(keep_generations()?)
+}
+
+/// Deletes all but the `keep` most recent previous generations, along with
+/// any bottles that are no longer used.
+pub fn cleanup(keep: usize) -> anyhow::Result<()> {
+    prune(keep)
+}
+
+fn prune(keep: usize) -> anyhow::Result<()> {
+    let generations = Generation::get_all()?;
+    // The current generation is always kept
+    let (old, kept) = generations.split_at(generations.len().saturating_sub(keep + 1));
+    for generation in old {
+        generation.delete()?;
+    }
+
+    let mut in_use = DownloadedBottle::get_installed()?
+        .iter()
+        .map(|b| b.id())
+        .collect::<BTreeSet<_>>();
+    for generation in kept {
+        in_use.extend(generation.bottle_ids()?);
+    }
+    for bottle in DownloadedBottle::get_all()? {
+        if !in_use.contains(&bottle.id()) {
+            bottle.unlink_files()?;
+            bottle.remove()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the path and target of each link for the installed bottles.
+fn current_links() -> anyhow::Result<BTreeMap<PathBuf, PathBuf>> {
+    let mut links = BTreeMap::new();
+    for bottle in DownloadedBottle::get_installed()? {
+        for linked_file in bottle.linked_files()? {
+            if let Ok(target) = fs::read_link(linked_file.path()) {
+                links.insert(linked_file.path().to_owned(), target);
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+fn keep_generations() -> anyhow::Result<usize> {
+    match env::var(KEEP_GENERATIONS_VAR) {
+        Ok(value) => value
+            .parse()
+            .with_context(|| format!("${KEEP_GENERATIONS_VAR} must be a number")),
+        Err(_) => Ok(DEFAULT_KEEP_GENERATIONS),
+    }
+}
+
+/// Points `link` at `target` by renaming a new symlink over it. Files that
+/// were not created by chug are left alone.
+fn replace_link(target: &Path, link: &Path) -> anyhow::Result<()> {
+    if fs::symlink_metadata(link).is_ok() && !is_chug_link(link)? {
+        println!("Skipping {}, which is not managed by chug", link.display());
+        return Ok(());
+    }
+
+    let parent = link.parent().context("Link path has no parent")?;
+    let file_name = link.file_name().context("Link path has no file name")?;
+    fs::create_dir_all(parent)?;
+
+    let temp = parent.join(format!(".{}.chug-tmp", file_name.to_string_lossy()));
+    let _ = fs::remove_file(&temp);
+    unix::fs::symlink(target, &temp)?;
+    fs::rename(&temp, link)?;
+
+    Ok(())
+}
+
+fn remove_link(link: &Path) -> anyhow::Result<()> {
+    if is_chug_link(link)? {
+        fs::remove_file(link)?;
+    }
+
+    Ok(())
+}
+
+fn is_chug_link(path: &Path) -> anyhow::Result<bool> {
+    let bottles_dir = dirs::bottles_dir()?;
+    Ok(fs::read_link(path).is_ok_and(|target| target.starts_with(bottles_dir)))
+}
@@ -0,0 +1,183 @@
+//! Describes a formula, and whether it is downloaded
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    db::models::{DownloadedBottle, PinnedFormula},
+    dirs,
+    formulae::Formula,
+    status::print_list,
+    target::Target,
+};
+
+/// Homebrew writes its prefix in caveats as this.
+const HOMEBREW_PREFIX_VAR: &str = "$HOMEBREW_PREFIX";
+
+#[derive(Debug, Serialize)]
+struct FormulaInfo<'a> {
+    name: &'a str,
+    desc: Option<&'a str>,
+    homepage: Option<&'a str>,
+    license: Option<&'a str>,
+    version: &'a str,
+    /// The targets that bottles are available for.
+    targets: Vec<&'a str>,
+    current_target: &'a str,
+    /// Whether a bottle is available for the current target.
+    current_target_available: bool,
+    dependencies: Vec<DependencyInfo<'a>>,
+    /// With Homebrew's `$HOMEBREW_PREFIX` replaced by chug's.
+    caveats: Option<String>,
+    /// Why the formula is not linked, if it is keg-only.
+    keg_only_reason: Option<String>,
+    installed: Option<InstalledInfo<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct DependencyInfo<'a> {
+    name: &'a str,
+    installed_version: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct InstalledInfo<'a> {
+    version: &'a str,
+    path: &'a Path,
+    pinned: bool,
+}
+
+/// Prints what is known about a formula, whether or not it is downloaded.
+pub fn info(name: &str, json: bool) -> anyhow::Result<()> {
+    let formula = Formula::get(name)?;
+    let installed = DownloadedBottle::get_installed()?;
+    let pinned = PinnedFormula::get_all()?;
+    let get_installed = |name: &str| installed.iter().find(|b| b.name() == name);
+    let prefix = dirs::data_dir()?.to_string_lossy();
+
+    let info = FormulaInfo {
+        name: &formula.name,
+        desc: formula.desc.as_deref(),
+        homepage: formula.homepage.as_deref(),
+        license: formula.license.as_deref(),
+        version: &formula.versions.stable,
+        targets: formula
+            .bottle
+            .stable
+            .files
+            .keys()
+            .map(String::as_str)
+            .collect(),
+        current_target: Target::current_str()?,
+        current_target_available: formula.versions.bottle
+            && formula.bottle.stable.current_target().is_ok(),
+        dependencies: formula
+            .dependencies
+            .iter()
+            .map(|name| DependencyInfo {
+                name,
+                installed_version: get_installed(name).map(|b| b.version()),
+            })
+            .collect(),
+        caveats: formula
+            .caveats
+            .as_ref()
+            .map(|caveats| caveats.replace(HOMEBREW_PREFIX_VAR, &prefix)),
+        keg_only_reason: formula.keg_only_reason(),
+        installed: get_installed(&formula.name).map(|b| InstalledInfo {
+            version: b.version(),
+            path: b.path(),
+            pinned: pinned.iter().any(|p| p.name() == b.name()),
+        }),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        info.print()?;
+    }
+
+    Ok(())
+}
+
+impl FormulaInfo<'_> {
+    fn print(&self) -> anyhow::Result<()> {
+        println!("{} {}", self.name, self.version);
+        if let Some(desc) = self.desc {
+            println!("{desc}");
+        }
+        if let Some(homepage) = self.homepage {
+            println!("{homepage}");
+        }
+        if let Some(license) = self.license {
+            println!("License: {license}");
+        }
+        println!();
+
+        if self.targets.is_empty() {
+            println!("No bottles are available");
+        } else {
+            println!("Bottles:");
+            print_list(&self.targets)?;
+        }
+        if self.current_target_available {
+            println!(
+                "A bottle is available for this platform ({})",
+                self.current_target
+            );
+        } else {
+            println!(
+                "No bottle is available for this platform ({})",
+                self.current_target
+            );
+        }
+        println!();
+
+        if !self.dependencies.is_empty() {
+            println!("Dependencies:");
+            for dependency in &self.dependencies {
+                match dependency.installed_version {
+                    Some(version) => println!("  {} ({version} installed)", dependency.name),
+                    None => println!("  {} (not installed)", dependency.name),
+                }
+            }
+            println!();
+        }
+
+        if let Some(reason) = &self.keg_only_reason {
+            println!("Keg-only, because {reason}.");
+            println!();
+        }
+        if let Some(caveats) = &self.caveats {
+            println!("Caveats:");
+            // They come from the formula, so keep them from sending escape
+            // sequences to the terminal
+            for line in caveats.trim_end().lines() {
+                let line = line
+                    .chars()
+                    .filter(|&c| c == '\t' || !c.is_control())
+                    .collect::<String>();
+                println!("  {line}");
+            }
+            println!();
+        }
+
+        match &self.installed {
+            Some(installed) => {
+                print!(
+                    "Installed {} at {}",
+                    installed.version,
+                    installed.path.display()
+                );
+                if installed.pinned {
+                    print!(" (pinned)");
+                }
+                println!();
+            }
+            None => println!("Not installed"),
+        }
+
+        Ok(())
+    }
+}
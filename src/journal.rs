@@ -0,0 +1,152 @@
+//! Lets chug recover when it is interrupted part way through changing bottles
+
+use std::{
+    collections::BTreeSet,
+    fs::{File, TryLockError},
+};
+
+use anyhow::Context;
+
+use crate::{
+    db::models::{DownloadedBottle, Journal, PinnedFormula, UnlinkedFormula},
+    dirs, generations,
+};
+
+/// Waits until no other chug process is running, so that a journal left by a
+/// running process is never mistaken for an interrupted one. The lock is held
+/// until chug exits.
+pub fn lock() -> anyhow::Result<()> {
+    cache!(File).get_or_init(|| {
+        let file = File::create(dirs::lock_file()?)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                println!("Waiting for another chug process to finish...");
+                file.lock()?;
+            }
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        Ok(file)
+    })?;
+
+    Ok(())
+}
+
+/// Finishes or undoes an action that was interrupted, e.g. by Ctrl-C, so that
+/// the DB and the files on disk agree again.
+pub fn recover() -> anyhow::Result<()> {
+    lock()?;
+
+    let Some(journal) = Journal::get()? else {
+        return Ok(());
+    };
+    let result = if journal.is_committed() {
+        println!("Finishing an action that was interrupted...");
+        finish(&journal)
+    } else {
+        println!("Undoing an action that was interrupted...");
+        undo(&journal)
+    };
+    println!();
+
+    result.context("Failed to recover. Run `chug rebuild-db` to start again")
+}
+
+/// For commands that only read the DB, which don't wait for the lock. Warns if
+/// an action is running or was interrupted, as what they show may be out of
+/// date until it is finished or undone.
+pub fn warn_if_pending() -> anyhow::Result<()> {
+    if Journal::get()?.is_some() {
+        eprintln!(
+            "Warning: another chug process is running or was interrupted, so this may be out of date"
+        );
+        eprintln!();
+    }
+
+    Ok(())
+}
+
+/// Restores the generation from before the action.
+pub fn undo(journal: &Journal) -> anyhow::Result<()> {
+    generations::restore(&journal.generation()?)?;
+
+    journal.delete()
+}
+
+/// Unlinks the bottles that the action replaced, and records the installed
+/// bottles as a new generation.
+pub fn finish(journal: &Journal) -> anyhow::Result<()> {
+    let installed = DownloadedBottle::get_installed()?;
+    let installed_ids = installed.iter().map(|b| b.id()).collect::<BTreeSet<_>>();
+    for id in journal.generation()?.bottle_ids()? {
+        if !installed_ids.contains(&id) {
+            DownloadedBottle::get_by_id(id)?.unlink()?;
+        }
+    }
+
+    let names = installed.iter().map(|b| b.name()).collect::<Vec<_>>();
+    UnlinkedFormula::delete_except(&names)?;
+    PinnedFormula::delete_except(&names)?;
+
+    journal.delete()?;
+    generations::record()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::recover;
+    use crate::{
+        db::models::{Dependency, DownloadedBottle, Generation, Journal},
+        dirs, generations,
+        links::LinkPlan,
+        testing,
+    };
+
+    /// Adds and links `foo`, like an action that is part way through.
+    fn add_bottle(version: &str) -> DownloadedBottle {
+        let path = dirs::bottles_dir().unwrap().join("foo").join(version);
+        fs::create_dir_all(path.join("bin")).unwrap();
+        fs::write(path.join("bin/foo"), "").unwrap();
+
+        let bottle = DownloadedBottle::create("foo", version, &path, None, None, &[]).unwrap();
+        bottle
+            .link(&LinkPlan::new([&bottle], false).unwrap())
+            .unwrap();
+        Dependency::replace_all([(None, &bottle)].into_iter()).unwrap();
+
+        bottle
+    }
+
+    // Both cases share the DB, and there can only be one journal at a time
+    #[test]
+    fn recover_undoes_or_finishes_interrupted_action() {
+        testing::home();
+        let link = dirs::bin_dir().unwrap().join("foo");
+
+        // Interrupted before it was committed, so it is undone
+        Journal::begin(&generations::checkpoint().unwrap()).unwrap();
+        add_bottle("1.0");
+        recover().unwrap();
+
+        assert!(Journal::get().unwrap().is_none());
+        assert!(DownloadedBottle::get_installed().unwrap().is_empty());
+        assert!(fs::symlink_metadata(&link).is_err());
+
+        // Interrupted after it was committed, so it is finished
+        let mut journal = Journal::begin(&generations::checkpoint().unwrap()).unwrap();
+        let bottle = add_bottle("2.0");
+        journal.commit().unwrap();
+        recover().unwrap();
+
+        assert!(Journal::get().unwrap().is_none());
+        let installed = DownloadedBottle::get_installed().unwrap();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].version(), "2.0");
+        assert_eq!(fs::read_link(&link).unwrap(), bottle.path().join("bin/foo"),);
+        let latest = Generation::get_all().unwrap().pop().unwrap();
+        assert_eq!(latest.bottle_ids().unwrap(), [bottle.id()]);
+    }
+}
@@ -3,10 +3,23 @@ mod cache;
 mod db;
 mod dirs;
 mod extract;
+mod manifest;
+mod receipt;
 mod status;
 mod target;
+#[cfg(test)]
+mod testing;
 
 pub mod action_builder;
 pub mod bottles;
+pub mod doctor;
 pub mod formulae;
+pub mod generations;
+pub mod info;
+pub mod journal;
+pub mod links;
+pub mod pins;
+pub mod plan;
+pub mod rebuild;
 pub mod tree;
+pub mod verify;
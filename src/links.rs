@@ -0,0 +1,503 @@
+//! Decides which bottle each linked file, such as those in the bin dir, should
+//! link to
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    db::models::{DownloadedBottle, ForcedLink, LinkPreference, UnlinkedFormula},
+    dirs,
+};
+
+#[derive(Debug)]
+pub struct LinkPlan<'a> {
+    /// The bottle and source path for each link, keyed by the link's path.
+    owners: BTreeMap<PathBuf, (&'a DownloadedBottle, PathBuf)>,
+    conflicts: Vec<Conflict<'a>>,
+    /// Bottles that are not linked, as they are keg-only.
+    keg_only: Vec<&'a DownloadedBottle>,
+    /// Bottles that are not linked, as the user ran `chug unlink`.
+    unlinked: Vec<&'a DownloadedBottle>,
+}
+
+/// A directory inside bottles whose contents are linked into `dest`.
+struct LinkDir {
+    source: &'static str,
+    dest: fn() -> anyhow::Result<&'static Path>,
+    /// Link the contents of each subdirectory, e.g. `man1`, rather than the
+    /// subdirectories themselves.
+    nested: bool,
+}
+
+const LINK_DIRS: &[LinkDir] = &[
+    LinkDir {
+        source: "bin",
+        dest: dirs::bin_dir,
+        nested: false,
+    },
+    LinkDir {
+        source: "sbin",
+        dest: dirs::bin_dir,
+        nested: false,
+    },
+    LinkDir {
+        source: "share/man",
+        dest: dirs::man_dir,
+        nested: true,
+    },
+    LinkDir {
+        source: "etc/bash_completion.d",
+        dest: dirs::bash_completions_dir,
+        nested: false,
+    },
+    LinkDir {
+        source: "share/bash-completion/completions",
+        dest: dirs::bash_completions_dir,
+        nested: false,
+    },
+    LinkDir {
+        source: "share/fish/vendor_completions.d",
+        dest: dirs::fish_completions_dir,
+        nested: false,
+    },
+    LinkDir {
+        source: "share/zsh/site-functions",
+        dest: dirs::zsh_completions_dir,
+        nested: false,
+    },
+    LinkDir {
+        source: "lib/pkgconfig",
+        dest: dirs::pkgconfig_dir,
+        nested: false,
+    },
+    LinkDir {
+        source: "share/pkgconfig",
+        dest: dirs::pkgconfig_dir,
+        nested: false,
+    },
+    LinkDir {
+        source: "share/aclocal",
+        dest: dirs::aclocal_dir,
+        nested: false,
+    },
+];
+
+#[derive(Debug)]
+enum Conflict<'a> {
+    /// Several bottles provide a file with the same name.
+    Bottles {
+        path: PathBuf,
+        winner: &'a DownloadedBottle,
+        losers: Vec<&'a DownloadedBottle>,
+        preferred: bool,
+    },
+    /// A file that was not created by chug is in the way.
+    Foreign { path: PathBuf, overwrite: bool },
+    /// A directory is in the way, which is never replaced.
+    Directory { path: PathBuf },
+}
+
+impl<'a> LinkPlan<'a> {
+    /// Plans the links for `bottles`, which should be every bottle that will
+    /// be downloaded once the current action is complete.
+    pub fn new(
+        bottles: impl IntoIterator<Item = &'a DownloadedBottle>,
+        overwrite: bool,
+    ) -> anyhow::Result<Self> {
+        let forced_links = ForcedLink::get_all()?;
+        let forced_links = forced_links.iter().map(|l| l.name()).collect();
+
+        Self::with_forced_links(bottles, overwrite, &forced_links)
+    }
+
+    /// Like `new`, but links the keg-only formulae in `forced_links` rather
+    /// than those saved in the DB.
+    pub fn with_forced_links(
+        bottles: impl IntoIterator<Item = &'a DownloadedBottle>,
+        overwrite: bool,
+        forced_links: &BTreeSet<&str>,
+    ) -> anyhow::Result<Self> {
+        let bottles_dir = dirs::bottles_dir()?;
+        let preferences = LinkPreference::get_all()?;
+        let unlinked_formulae = UnlinkedFormula::get_all()?;
+
+        let mut candidates = BTreeMap::<_, Vec<_>>::new();
+        let mut keg_only = Vec::new();
+        let mut unlinked = Vec::new();
+        for bottle in bottles {
+            if unlinked_formulae.iter().any(|f| f.name() == bottle.name()) {
+                unlinked.push(bottle);
+                continue;
+            }
+            if bottle.keg_only_reason().is_some() && !forced_links.contains(bottle.name()) {
+                keg_only.push(bottle);
+                continue;
+            }
+
+            for (source, dest) in bottle_links(bottle)? {
+                candidates.entry(dest).or_default().push((bottle, source));
+            }
+        }
+
+        let mut owners = BTreeMap::new();
+        let mut conflicts = Vec::new();
+        for (dest, mut candidates) in candidates {
+            if fs::symlink_metadata(&dest).is_ok_and(|m| m.is_dir()) {
+                conflicts.push(Conflict::Directory { path: dest });
+                continue;
+            }
+
+            let existing = fs::read_link(&dest).ok();
+            let is_foreign = fs::symlink_metadata(&dest).is_ok()
+                && !existing
+                    .as_ref()
+                    .is_some_and(|t| t.starts_with(bottles_dir));
+            if is_foreign {
+                conflicts.push(Conflict::Foreign {
+                    path: dest.clone(),
+                    overwrite,
+                });
+                if !overwrite {
+                    continue;
+                }
+            }
+
+            if candidates.len() == 1 {
+                owners.insert(dest, candidates.remove(0));
+                continue;
+            }
+
+            let names = candidates.iter().map(|(b, _)| b.name()).collect::<Vec<_>>();
+            // Preferences are by file name, e.g. `python3`
+            let preference = preferences
+                .iter()
+                .find(|p| dest.file_name().is_some_and(|n| n == p.name()))
+                .map(|p| p.formula());
+            let current =
+                existing.and_then(|t| candidates.iter().position(|(b, _)| t.starts_with(b.path())));
+            let (index, preferred) = choose(&names, preference, current);
+
+            let winner = candidates.remove(index);
+            conflicts.push(Conflict::Bottles {
+                path: dest.clone(),
+                winner: winner.0,
+                losers: candidates.into_iter().map(|(b, _)| b).collect(),
+                preferred,
+            });
+            owners.insert(dest, winner);
+        }
+
+        Ok(Self {
+            owners,
+            conflicts,
+            keg_only,
+            unlinked,
+        })
+    }
+
+    pub fn print_conflicts(&self) {
+        if self.conflicts.is_empty() {
+            return;
+        }
+
+        println!("Link conflicts:");
+        for conflict in &self.conflicts {
+            println!("  {conflict}");
+        }
+        println!();
+    }
+
+    /// Returns the reason `bottle` is not linked, if it is keg-only.
+    pub fn keg_only_reason<'b>(&self, bottle: &'b DownloadedBottle) -> Option<&'b str> {
+        if !self.keg_only.iter().any(|b| b.id() == bottle.id()) {
+            return None;
+        }
+        bottle.keg_only_reason()
+    }
+
+    pub fn is_unlinked(&self, bottle: &DownloadedBottle) -> bool {
+        self.unlinked.iter().any(|b| b.id() == bottle.id())
+    }
+
+    /// Returns the source and destination of each link that `bottle` should
+    /// create.
+    pub fn links_for(&self, bottle: &DownloadedBottle) -> impl Iterator<Item = (&Path, &Path)> {
+        self.owners
+            .iter()
+            .filter(move |(_, (b, _))| b.id() == bottle.id())
+            .map(|(dest, (_, source))| (source.as_path(), dest.as_path()))
+    }
+}
+
+/// Returns the source and destination of each link that `bottle` could
+/// create.
+fn bottle_links(bottle: &DownloadedBottle) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    links_in(bottle.path())
+}
+
+/// Several source dirs share a destination, e.g. `bin` and `sbin`, so a file
+/// in both is only linked from the first, rather than conflicting with itself.
+fn links_in(bottle_path: &Path) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut links = Vec::new();
+    let mut dests = BTreeSet::new();
+    for link_dir in LINK_DIRS {
+        let source_dir = bottle_path.join(link_dir.source);
+        if !source_dir.is_dir() {
+            continue;
+        }
+        let dest_dir = (link_dir.dest)()?;
+
+        for entry in fs::read_dir(&source_dir)? {
+            let entry = entry?;
+            if !link_dir.nested {
+                let dest = dest_dir.join(entry.file_name());
+                if dests.insert(dest.clone()) {
+                    links.push((entry.path(), dest));
+                }
+                continue;
+            }
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let dest_subdir = dest_dir.join(entry.file_name());
+            for entry in fs::read_dir(entry.path())? {
+                let entry = entry?;
+                let dest = dest_subdir.join(entry.file_name());
+                if dests.insert(dest.clone()) {
+                    links.push((entry.path(), dest));
+                }
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+/// Returns the existing directories that links could be in.
+pub fn link_dirs() -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = BTreeSet::new();
+    for link_dir in LINK_DIRS {
+        let dest_dir = (link_dir.dest)()?;
+        if !dest_dir.is_dir() {
+            continue;
+        }
+        if !link_dir.nested {
+            paths.insert(dest_dir.to_owned());
+            continue;
+        }
+
+        for entry in fs::read_dir(dest_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                paths.insert(entry.path());
+            }
+        }
+    }
+
+    Ok(paths.into_iter().collect())
+}
+
+/// Returns the index of the formula that should win a conflict, and whether
+/// that was due to the user's preference. Otherwise, the formula that is
+/// already linked wins, falling back to the first by name.
+fn choose(names: &[&str], preference: Option<&str>, current: Option<usize>) -> (usize, bool) {
+    if let Some(index) = preference.and_then(|p| names.iter().position(|&n| n == p)) {
+        return (index, true);
+    }
+    if let Some(index) = current {
+        return (index, false);
+    }
+
+    let index = (0..names.len()).min_by_key(|&i| names[i]).unwrap_or(0);
+    (index, false)
+}
+
+/// Sets which formula should be linked as `name` when several provide it, or
+/// clears the preference if `formula` is `None`. The links are updated to
+/// match.
+pub fn prefer(name: &str, formula: Option<&str>) -> anyhow::Result<()> {
+    match formula {
+        Some(formula) => {
+            let mut provides = false;
+            for bottle in DownloadedBottle::get_by_names(&[formula.to_owned()])? {
+                provides |= bottle_links(&bottle)?
+                    .iter()
+                    .any(|(_, dest)| dest.file_name().is_some_and(|n| n == name));
+            }
+            anyhow::ensure!(provides, "{formula} does not provide {name}");
+
+            LinkPreference::set(name, formula)?;
+        }
+        None => LinkPreference::clear(name)?,
+    }
+
+    relink_all()
+}
+
+/// Links bottles, undoing `unlink`. Keg-only bottles are linked as well.
+pub fn link(names: &[String]) -> anyhow::Result<()> {
+    for bottle in DownloadedBottle::get_by_names(names)? {
+        UnlinkedFormula::delete(bottle.name())?;
+        if bottle.keg_only_reason().is_some() {
+            ForcedLink::create(bottle.name())?;
+        }
+    }
+
+    relink_all()
+}
+
+/// Removes the links for bottles, and keeps them unlinked when they are
+/// updated. Any files they won a conflict for are linked from the other
+/// bottles instead.
+pub fn unlink(names: &[String]) -> anyhow::Result<()> {
+    for bottle in DownloadedBottle::get_by_names(names)? {
+        println!("Unlinking {} {}...", bottle.name(), bottle.version());
+        UnlinkedFormula::create(bottle.name())?;
+        bottle.unlink_files()?;
+    }
+
+    relink_all()
+}
+
+/// Updates the links for every downloaded bottle to match the current plan.
+fn relink_all() -> anyhow::Result<()> {
+    let bottles = DownloadedBottle::get_installed()?;
+    let plan = LinkPlan::new(&bottles, false)?;
+    plan.print_conflicts();
+    for bottle in &bottles {
+        bottle.link_files(&plan)?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Conflict<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Conflict::Bottles {
+                path,
+                winner,
+                losers,
+                preferred,
+            } => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let losers = losers.iter().map(|b| b.name()).collect::<Vec<_>>();
+                write!(
+                    f,
+                    "{name} is provided by {} and {}",
+                    winner.name(),
+                    losers.join(", "),
+                )?;
+                if *preferred {
+                    write!(f, ", linking {} as preferred", winner.name())
+                } else {
+                    write!(
+                        f,
+                        ", linking {}. Run `chug prefer {name} <formula>` to choose",
+                        winner.name(),
+                    )
+                }
+            }
+            Conflict::Foreign {
+                path,
+                overwrite: true,
+            } => write!(
+                f,
+                "Replacing {}, which is not managed by chug",
+                path.display()
+            ),
+            Conflict::Foreign {
+                path,
+                overwrite: false,
+            } => write!(
+                f,
+                "Skipping {}, which is not managed by chug. Use --overwrite to replace it",
+                path.display(),
+            ),
+            Conflict::Directory { path } => {
+                write!(f, "Skipping {}, which is a directory", path.display(),)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{choose, links_in};
+    use crate::{
+        dirs,
+        testing::{self, TempDir},
+    };
+
+    #[test]
+    fn choose_prefers_preference_then_current_then_name() {
+        let names = ["python@3.13", "python@3.12"];
+
+        assert_eq!(choose(&names, Some("python@3.13"), Some(1)), (0, true));
+        assert_eq!(choose(&names, Some("python@3.11"), Some(0)), (0, false));
+        assert_eq!(choose(&names, None, Some(0)), (0, false));
+        assert_eq!(choose(&names, None, None), (1, false));
+    }
+
+    #[test]
+    fn links_man_pages_by_section() {
+        testing::home();
+        let bottle = TempDir::new("man-pages");
+        let man = bottle.path().join("share/man");
+        fs::create_dir_all(man.join("man1")).unwrap();
+        fs::create_dir_all(man.join("man5")).unwrap();
+        fs::write(man.join("man1/foo.1"), "").unwrap();
+        fs::write(man.join("man5/foo.conf.5"), "").unwrap();
+        // Only the contents of each section are linked
+        fs::write(man.join("whatis"), "").unwrap();
+
+        let mut links = links_in(bottle.path()).unwrap();
+        links.sort();
+
+        let man_dir = dirs::man_dir().unwrap();
+        assert_eq!(
+            links,
+            [
+                (man.join("man1/foo.1"), man_dir.join("man1/foo.1")),
+                (man.join("man5/foo.conf.5"), man_dir.join("man5/foo.conf.5")),
+            ],
+        );
+    }
+
+    #[test]
+    fn links_file_in_bin_and_sbin_once() {
+        testing::home();
+        let bottle = TempDir::new("bin-and-sbin");
+        for dir in ["bin", "sbin", "lib/pkgconfig", "share/pkgconfig"] {
+            fs::create_dir_all(bottle.path().join(dir)).unwrap();
+        }
+        fs::write(bottle.path().join("bin/foo"), "").unwrap();
+        fs::write(bottle.path().join("sbin/foo"), "").unwrap();
+        fs::write(bottle.path().join("sbin/food"), "").unwrap();
+        fs::write(bottle.path().join("lib/pkgconfig/foo.pc"), "").unwrap();
+        fs::write(bottle.path().join("share/pkgconfig/foo.pc"), "").unwrap();
+
+        let mut links = links_in(bottle.path()).unwrap();
+        links.sort();
+
+        let bin_dir = dirs::bin_dir().unwrap();
+        let pkgconfig_dir = dirs::pkgconfig_dir().unwrap();
+        assert_eq!(
+            links,
+            [
+                (bottle.path().join("bin/foo"), bin_dir.join("foo")),
+                (
+                    bottle.path().join("lib/pkgconfig/foo.pc"),
+                    pkgconfig_dir.join("foo.pc"),
+                ),
+                (bottle.path().join("sbin/food"), bin_dir.join("food")),
+            ],
+        );
+    }
+}
@@ -1,8 +1,16 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
 use chug_cli::{
     action_builder::{ActionBuilder, BottleForestSnapshot},
+    doctor::doctor,
+    generations::{cleanup, rollback},
+    info::info,
+    journal::{recover, warn_if_pending},
+    links::{link, prefer, unlink},
+    pins::{pin, unpin},
+    rebuild::rebuild_db,
     tree::{display_tree, list_bottles},
+    verify::verify_bottles,
 };
 
 #[derive(Parser)]
@@ -17,6 +25,14 @@ enum Commands {
     Add {
         /// Bottles to add.
         bottles: Vec<String>,
+        /// Replace files in the bin dir that were not created by chug.
+        #[arg(long)]
+        overwrite: bool,
+        /// Link the bottles even if they are keg-only.
+        #[arg(long)]
+        force_link: bool,
+        #[command(flatten)]
+        plan: PlanArgs,
     },
     /// Unlink and remove bottles.
     Remove {
@@ -25,44 +41,186 @@ enum Commands {
         /// Remove all downloaded bottles.
         #[arg(long)]
         all: bool,
+        #[command(flatten)]
+        plan: PlanArgs,
     },
     /// Update already-downloaded bottles.
-    Update,
+    Update {
+        /// Replace files in the bin dir that were not created by chug.
+        #[arg(long)]
+        overwrite: bool,
+        #[command(flatten)]
+        plan: PlanArgs,
+    },
+    /// Switch back to the bottles and links from before the last add, remove
+    /// or update.
+    Rollback,
+    /// Delete previous generations, and the bottles only they use.
+    Cleanup {
+        /// Number of previous generations to keep.
+        #[arg(long, default_value_t = 0)]
+        keep: usize,
+    },
+    /// Show details of a formula, and whether it is installed.
+    Info {
+        /// The formula to show.
+        formula: String,
+        /// Print the details as JSON.
+        #[arg(long)]
+        json: bool,
+    },
     /// List all downloaded bottles.
     List,
     /// Display a tree of all downloaded bottles.
     Tree,
+    /// Check the DB and the installed files for inconsistencies.
+    Doctor {
+        /// Repair any problems that are found.
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Link bottles that were unlinked, or that are keg-only.
+    Link {
+        /// Bottles to link.
+        bottles: Vec<String>,
+    },
+    /// Unlink bottles, keeping them unlinked when they are updated.
+    Unlink {
+        /// Bottles to unlink.
+        bottles: Vec<String>,
+    },
+    /// Keep bottles at their current version when updating.
+    Pin {
+        /// Bottles to pin.
+        bottles: Vec<String>,
+    },
+    /// Allow pinned bottles to be updated again.
+    Unpin {
+        /// Bottles to unpin.
+        bottles: Vec<String>,
+    },
+    /// Choose which formula to link a file from when several provide it.
+    Prefer {
+        /// The name of the file in the bin dir, e.g. `python3`.
+        name: String,
+        /// The formula to link it from. Omit to clear the preference.
+        formula: Option<String>,
+    },
+    /// Recreate the DB from the bottles and links on disk.
+    RebuildDb,
+    /// Check downloaded bottles for missing, modified or extra files.
+    Verify {
+        /// Bottles to verify. Defaults to all downloaded bottles.
+        bottles: Vec<String>,
+    },
+}
+
+#[derive(Args)]
+struct PlanArgs {
+    /// Print what would change, without changing anything.
+    #[arg(long)]
+    dry_run: bool,
+    /// Print the plan as JSON.
+    #[arg(long, requires = "dry_run")]
+    json: bool,
+}
+
+impl PlanArgs {
+    fn run(&self, mut action: ActionBuilder) -> anyhow::Result<()> {
+        if !self.dry_run {
+            return action.run();
+        }
+
+        let plan = action.plan()?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&plan)?);
+        } else {
+            plan.print()?;
+        }
+
+        Ok(())
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Add { bottles } => {
+        // `rebuild-db` must move the DB aside before anything opens it
+        Commands::RebuildDb => {}
+        // Commands that only read, including dry runs, report an interrupted
+        // action rather than acting on it
+        Commands::Add {
+            plan: PlanArgs { dry_run: true, .. },
+            ..
+        }
+        | Commands::Remove {
+            plan: PlanArgs { dry_run: true, .. },
+            ..
+        }
+        | Commands::Update {
+            plan: PlanArgs { dry_run: true, .. },
+            ..
+        }
+        | Commands::Info { .. }
+        | Commands::List
+        | Commands::Tree
+        | Commands::Verify { .. }
+        | Commands::Doctor { fix: false } => warn_if_pending()?,
+        _ => recover()?,
+    }
+
+    match cli.command {
+        Commands::Add {
+            bottles,
+            overwrite,
+            force_link,
+            plan,
+        } => {
             let snapshot = BottleForestSnapshot::new()?;
-            ActionBuilder::new(&snapshot).add_bottles(&bottles)?.run()?;
+            let action = ActionBuilder::new(&snapshot)?
+                .overwrite(overwrite)
+                .force_link(force_link)
+                .add_bottles(&bottles)?;
+            plan.run(action)?;
         }
-        Commands::Remove { all: true, bottles } => {
+        Commands::Remove {
+            all: true,
+            bottles,
+            plan,
+        } => {
             anyhow::ensure!(
                 bottles.is_empty(),
                 "Cannot specify bottles when --all is used",
             );
 
             let snapshot = BottleForestSnapshot::new()?;
-            ActionBuilder::new(&snapshot).remove_all().run()?;
+            plan.run(ActionBuilder::new(&snapshot)?.remove_all())?;
         }
         Commands::Remove {
             bottles,
             all: false,
+            plan,
         } => {
             let snapshot = BottleForestSnapshot::new()?;
-            ActionBuilder::new(&snapshot)
-                .remove_bottles(&bottles)?
-                .run()?;
+            plan.run(ActionBuilder::new(&snapshot)?.remove_bottles(&bottles)?)?;
         }
-        Commands::Update => {
+        Commands::Update { overwrite, plan } => {
             let snapshot = BottleForestSnapshot::new()?;
-            ActionBuilder::new(&snapshot).update()?.run()?;
+            plan.run(
+                ActionBuilder::new(&snapshot)?
+                    .overwrite(overwrite)
+                    .update()?,
+            )?;
+        }
+        Commands::Rollback => {
+            rollback()?;
+        }
+        Commands::Cleanup { keep } => {
+            cleanup(keep)?;
+        }
+        Commands::Info { formula, json } => {
+            info(&formula, json)?;
         }
         Commands::List => {
             list_bottles()?;
@@ -70,6 +228,30 @@ fn main() -> anyhow::Result<()> {
         Commands::Tree => {
             display_tree()?;
         }
+        Commands::Doctor { fix } => {
+            doctor(fix)?;
+        }
+        Commands::Link { bottles } => {
+            link(&bottles)?;
+        }
+        Commands::Unlink { bottles } => {
+            unlink(&bottles)?;
+        }
+        Commands::Pin { bottles } => {
+            pin(&bottles)?;
+        }
+        Commands::Unpin { bottles } => {
+            unpin(&bottles)?;
+        }
+        Commands::Prefer { name, formula } => {
+            prefer(&name, formula.as_deref())?;
+        }
+        Commands::RebuildDb => {
+            rebuild_db()?;
+        }
+        Commands::Verify { bottles } => {
+            verify_bottles(&bottles)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,156 @@
+//! Records the files extracted for each bottle, so they can be checked later
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use data_encoding::HEXLOWER;
+use ring::digest;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+}
+
+#[derive(Debug)]
+pub struct ManifestEntry {
+    /// Relative to the bottle's root directory.
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub mode: u32,
+    /// For symlinks, this and the hash are of the link target.
+    pub size: u64,
+    pub sha256: Option<String>,
+    /// Whether any placeholders were replaced when extracting this file.
+    pub relocated: bool,
+}
+
+impl FileKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileKind::File => "file",
+            FileKind::Directory => "directory",
+            FileKind::Symlink => "symlink",
+            FileKind::HardLink => "hard_link",
+        }
+    }
+}
+
+impl FromStr for FileKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "file" => Ok(FileKind::File),
+            "directory" => Ok(FileKind::Directory),
+            "symlink" => Ok(FileKind::Symlink),
+            "hard_link" => Ok(FileKind::HardLink),
+            _ => anyhow::bail!("Unknown file kind: {s:?}"),
+        }
+    }
+}
+
+/// Returns the paths of everything inside `root`, relative to it.
+pub fn walk(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut stack = vec![root.to_owned()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                stack.push(path.clone());
+            }
+            if let Ok(relative) = path.strip_prefix(root) {
+                paths.push(relative.to_owned());
+            }
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Returns the size and SHA-256 to record for a file of the given kind.
+pub fn hash_entry(path: &Path, kind: FileKind) -> io::Result<(u64, Option<String>)> {
+    match kind {
+        FileKind::File | FileKind::HardLink => {
+            let (size, sha256) = hash_file(path)?;
+            Ok((size, Some(sha256)))
+        }
+        FileKind::Symlink => {
+            let target = fs::read_link(path)?;
+            let target = target.as_os_str().as_bytes();
+            Ok((target.len() as u64, Some(hash_bytes(target))))
+        }
+        FileKind::Directory => Ok((0, None)),
+    }
+}
+
+/// Hashes everything written through it, so that files can be hashed as they
+/// are extracted rather than read back afterwards.
+pub struct HashWriter<W> {
+    inner: W,
+    context: digest::Context,
+    size: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashWriter {
+            inner,
+            context: digest::Context::new(&digest::SHA256),
+            size: 0,
+        }
+    }
+
+    /// Flushes the writer and returns the size and SHA-256 of what was written.
+    pub fn finish(mut self) -> io::Result<(u64, String)> {
+        self.inner.flush()?;
+        Ok((self.size, HEXLOWER.encode(self.context.finish().as_ref())))
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.context.update(&buf[..len]);
+        self.size += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the size and SHA-256 of a file's contents.
+fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut size = 0;
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        context.update(&buffer[..len]);
+        size += len as u64;
+    }
+
+    Ok((size, HEXLOWER.encode(context.finish().as_ref())))
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    HEXLOWER.encode(digest::digest(&digest::SHA256, bytes).as_ref())
+}
@@ -0,0 +1,28 @@
+use anyhow::Context;
+
+use crate::db::models::{DownloadedBottle, PinnedFormula};
+
+/// Keeps bottles at their downloaded version when running `chug update`.
+pub fn pin(names: &[String]) -> anyhow::Result<()> {
+    for bottle in DownloadedBottle::get_by_names(names)? {
+        PinnedFormula::create(bottle.name())?;
+        println!("Pinned {} {}", bottle.name(), bottle.version());
+    }
+
+    Ok(())
+}
+
+pub fn unpin(names: &[String]) -> anyhow::Result<()> {
+    let pinned = PinnedFormula::get_all()?;
+
+    for name in names {
+        let pin = pinned
+            .iter()
+            .find(|p| p.name() == name)
+            .with_context(|| format!("{name} is not pinned"))?;
+        pin.delete()?;
+        println!("Unpinned {name}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! What an action will change, worked out before anything is downloaded
+
+use std::{fmt, io, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::status::{format_size, print_list};
+
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    /// Bottles for formulae that are not downloaded yet.
+    pub add: Vec<PlannedBottle>,
+    /// Formulae that will change to a different version.
+    pub upgrade: Vec<Upgrade>,
+    /// Bottles for formulae that will no longer be downloaded.
+    pub remove: Vec<PlannedBottle>,
+    /// Files that will be linked, such as those in the bin dir. The files in
+    /// bottles that are not downloaded yet are only known once they are, so
+    /// they are not included.
+    pub link: Vec<PathBuf>,
+    /// Files that will be unlinked.
+    pub unlink: Vec<PathBuf>,
+    /// The total size of the bottles to download, in bytes, if the size of
+    /// each of them is known.
+    pub download_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedBottle {
+    pub name: String,
+    pub version: String,
+    /// Whether the bottle needs to be downloaded, rather than being kept from
+    /// before.
+    pub download: bool,
+    /// The size of the bottle in bytes, if it needs to be downloaded and the
+    /// size could be fetched.
+    pub download_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Upgrade {
+    pub name: String,
+    pub old_version: String,
+    pub new_version: String,
+    /// Whether the new bottle needs to be downloaded.
+    pub download: bool,
+    /// The size of the new bottle in bytes, if it needs to be downloaded and
+    /// the size could be fetched.
+    pub download_size: Option<u64>,
+}
+
+impl Plan {
+    /// Plans the bottles that will be added and removed, treating those with
+    /// the same name as upgrades.
+    pub fn from_bottles(added: Vec<PlannedBottle>, removed: Vec<PlannedBottle>) -> Self {
+        let mut plan = Plan {
+            download_size: added
+                .iter()
+                .filter(|b| b.download)
+                .map(|b| b.download_size)
+                .sum(),
+            ..Plan::default()
+        };
+        for bottle in added {
+            match removed.iter().find(|b| b.name == bottle.name) {
+                Some(old) => plan.upgrade.push(Upgrade {
+                    name: bottle.name,
+                    old_version: old.version.clone(),
+                    new_version: bottle.version,
+                    download: bottle.download,
+                    download_size: bottle.download_size,
+                }),
+                None => plan.add.push(bottle),
+            }
+        }
+        plan.remove = removed
+            .into_iter()
+            .filter(|b| !plan.upgrade.iter().any(|u| u.name == b.name))
+            .collect();
+
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty()
+            && self.upgrade.is_empty()
+            && self.remove.is_empty()
+            && self.link.is_empty()
+            && self.unlink.is_empty()
+    }
+
+    /// Prints the bottles that will change, and how much will be downloaded.
+    pub fn print_bottles(&self) -> io::Result<()> {
+        if !self.add.is_empty() {
+            println!("Adding bottles:");
+            print_list(&self.add)?;
+            println!();
+        }
+        if !self.upgrade.is_empty() {
+            println!("Upgrading bottles:");
+            print_list(&self.upgrade)?;
+            println!();
+        }
+        if !self.remove.is_empty() {
+            println!("Removing bottles:");
+            print_list(&self.remove)?;
+            println!();
+        }
+        match self.download_size {
+            Some(0) => {}
+            Some(size) => {
+                println!("Download size: {}", format_size(size));
+                println!();
+            }
+            None => {
+                println!("Download size: unknown");
+                println!();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints everything that will change, including the linked files.
+    pub fn print(&self) -> io::Result<()> {
+        if self.is_empty() {
+            println!("Nothing to change");
+            return Ok(());
+        }
+
+        self.print_bottles()?;
+        if !self.link.is_empty() {
+            println!("Linking files:");
+            for path in &self.link {
+                println!("  {}", path.display());
+            }
+            println!();
+        }
+        if !self.unlink.is_empty() {
+            println!("Unlinking files:");
+            for path in &self.unlink {
+                println!("  {}", path.display());
+            }
+            println!();
+        }
+        let has_downloads =
+            self.add.iter().any(|b| b.download) || self.upgrade.iter().any(|u| u.download);
+        if has_downloads {
+            println!("Files in bottles that are not downloaded yet are not shown");
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for PlannedBottle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
+}
+
+impl fmt::Display for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} -> {}",
+            self.name, self.old_version, self.new_version
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plan, PlannedBottle};
+
+    fn bottle(name: &str, version: &str, download_size: Option<u64>) -> PlannedBottle {
+        PlannedBottle {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            download: download_size.is_some(),
+            download_size,
+        }
+    }
+
+    #[test]
+    fn pairs_bottles_with_the_same_name_as_upgrades() {
+        let plan = Plan::from_bottles(
+            vec![
+                bottle("curl", "8.13.0", Some(100)),
+                bottle("jq", "1.7.1", Some(20)),
+            ],
+            vec![
+                bottle("curl", "8.12.1", None),
+                bottle("wget", "1.25.0", None),
+            ],
+        );
+
+        let added = plan.add.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let upgraded = plan
+            .upgrade
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let removed = plan
+            .remove
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(added, ["jq 1.7.1"]);
+        assert_eq!(upgraded, ["curl 8.12.1 -> 8.13.0"]);
+        assert_eq!(removed, ["wget 1.25.0"]);
+        assert_eq!(plan.download_size, Some(120));
+    }
+
+    #[test]
+    fn download_size_is_unknown_if_any_size_is() {
+        let mut unknown = bottle("jq", "1.7.1", None);
+        unknown.download = true;
+        let plan = Plan::from_bottles(vec![bottle("curl", "8.13.0", Some(100)), unknown], vec![]);
+        assert_eq!(plan.download_size, None);
+
+        // Bottles that are already downloaded don't count
+        let plan = Plan::from_bottles(
+            vec![
+                bottle("curl", "8.13.0", Some(100)),
+                bottle("jq", "1.7.1", None),
+            ],
+            vec![],
+        );
+        assert_eq!(plan.download_size, Some(100));
+    }
+}
@@ -0,0 +1,294 @@
+//! Recovers the DB from the bottles and links on disk
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+
+use crate::{
+    db::models::{Dependency, DownloadedBottle, LinkedFile},
+    dirs,
+    formulae::Formula,
+    journal, links,
+    receipt::{self, InstallReceipt},
+};
+
+/// Moves the existing DB aside and re-creates it from the bottles dir.
+///
+/// File manifests are not recovered, as the files on disk can no longer be
+/// trusted to match what was extracted. Previous generations are not
+/// recovered either, so older versions kept for `chug rollback` are deleted
+/// by the next change.
+pub fn rebuild_db() -> anyhow::Result<()> {
+    journal::lock()?;
+
+    // This must happen before anything opens the DB
+    let db_file = dirs::db_file()?;
+    if db_file.exists() {
+        // Keep every earlier backup, in case the last rebuild went wrong too
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let backup = db_file.with_extension(format!("sqlite.{timestamp}.bak"));
+        anyhow::ensure!(
+            !backup.exists(),
+            "A backup of the DB already exists at {}",
+            backup.display(),
+        );
+        fs::rename(db_file, &backup).context("Failed to move the existing DB aside")?;
+        println!("Moved the existing DB to {}", backup.display());
+    }
+
+    let mut bottles = Vec::new();
+    for path in dirs::bottle_dirs()? {
+        let name = path
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|s| s.to_str())
+            .context("Bottle directory name is non-utf8")?;
+        let dir_version = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .context("Bottle directory name is non-utf8")?;
+
+        let receipt = InstallReceipt::read(&path)
+            .with_context(|| format!("Failed to read receipt for {name} {dir_version}"))?;
+        // The directory may have a revision suffix, e.g. `1.2.3_1`
+        let version = receipt
+            .as_ref()
+            .and_then(|r| r.source.as_ref()?.versions.as_ref()?.stable.as_deref())
+            .unwrap_or(dir_version);
+
+        if DownloadedBottle::get(name, version)?.is_some() {
+            println!(
+                "Skipping {}, as {name} {version} is already recovered",
+                path.display()
+            );
+            continue;
+        }
+
+        // Keg-only status isn't in the receipt, so fall back to the formula
+        let keg_only_reason = Formula::get_exact(name)
+            .ok()
+            .and_then(|f| f.keg_only_reason());
+        let bottle = DownloadedBottle::create(
+            name,
+            version,
+            &path,
+            keg_only_reason.as_deref(),
+            receipt.as_ref(),
+            &[],
+        )?;
+        println!("Recovered {name} {version}");
+
+        bottles.push((bottle, receipt));
+    }
+
+    for dir in links::link_dirs()? {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Ok(target) = fs::read_link(&path) else {
+                continue;
+            };
+            if let Some((bottle, _)) = bottles.iter().find(|(b, _)| target.starts_with(b.path())) {
+                LinkedFile::create(&path, bottle)?;
+            }
+        }
+    }
+
+    // Only one version of each formula can be installed: the one that the opt
+    // link points at, or else the newest
+    let opt_dir = dirs::opt_dir()?;
+    let mut versions_by_name = BTreeMap::<_, Vec<_>>::new();
+    for entry in &bottles {
+        versions_by_name
+            .entry(entry.0.name())
+            .or_default()
+            .push(entry);
+    }
+    let mut installed = Vec::new();
+    for (name, mut versions) in versions_by_name {
+        versions.sort_by(|(a, _), (b, _)| compare_versions(a.version(), b.version()));
+        let opt_target = fs::read_link(opt_dir.join(name)).ok();
+        let index = versions
+            .iter()
+            .position(|(bottle, _)| opt_target.as_deref() == Some(bottle.path()))
+            .unwrap_or(versions.len() - 1);
+        let chosen = versions.remove(index);
+        for (other, _) in versions {
+            eprintln!(
+                "Warning: Treating {name} {} as installed rather than {name} {}, which will be deleted by the next change",
+                chosen.0.version(),
+                other.version(),
+            );
+        }
+        installed.push(chosen);
+    }
+
+    let by_name = installed
+        .iter()
+        .map(|(bottle, _)| (bottle.name(), bottle))
+        .collect::<BTreeMap<_, _>>();
+    let receipts = installed
+        .iter()
+        .map(|(bottle, receipt)| (bottle.name(), receipt.as_ref()))
+        .collect::<Vec<_>>();
+    let dependencies = infer_dependencies(&receipts)
+        .into_iter()
+        .map(|(dependent, dependency)| (dependent.map(|name| by_name[name]), by_name[dependency]))
+        .collect::<Vec<_>>();
+    Dependency::replace_all(dependencies.into_iter())?;
+
+    println!(
+        "Rebuilt the DB with {} bottle(s). Run `chug doctor` to check it",
+        bottles.len(),
+    );
+
+    Ok(())
+}
+
+/// Returns the `(dependent, dependency)` pairs between the installed bottles,
+/// given by name along with their receipts. Roots, i.e. the bottles that must
+/// have been added by the user, have no dependent.
+fn infer_dependencies<'a>(
+    installed: &[(&'a str, Option<&InstallReceipt>)],
+) -> Vec<(Option<&'a str>, &'a str)> {
+    let names = installed
+        .iter()
+        .map(|&(name, _)| name)
+        .collect::<BTreeSet<_>>();
+
+    let mut dependencies = Vec::new();
+    let mut has_dependents = BTreeSet::new();
+    for &(name, receipt) in installed {
+        let runtime_dependencies = receipt
+            .and_then(|r| r.runtime_dependencies.as_deref())
+            .unwrap_or_default();
+        let direct_dependencies = receipt::direct_dependencies(
+            name,
+            runtime_dependencies
+                .iter()
+                .map(|d| (d.full_name.as_str(), d.declared_directly)),
+        );
+        for dependency in direct_dependencies {
+            if let Some(&dependency) = names.get(dependency) {
+                dependencies.push((Some(name), dependency));
+                has_dependents.insert(dependency);
+            }
+        }
+    }
+    // Bottles that nothing depends on must have been added by the user. The
+    // receipts' `installed_on_request` describes the machine the bottle was
+    // built on, so it says nothing about this user
+    for &(name, _) in installed {
+        if !has_dependents.contains(name) {
+            dependencies.push((None, name));
+        }
+    }
+
+    dependencies
+}
+
+/// Compares versions part by part, numerically where both parts are numbers,
+/// so that e.g. 1.10 is newer than 1.9.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let is_separator = |c: char| !c.is_ascii_alphanumeric();
+    let mut a = a.split(is_separator);
+    let mut b = b.split(is_separator);
+    loop {
+        let ordering = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{InstallReceipt, compare_versions, infer_dependencies};
+
+    fn receipt(json: &str) -> InstallReceipt {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn bottles_without_dependents_are_roots() {
+        let curl = receipt(
+            r#"{"runtime_dependencies": [
+                {"full_name": "ca-certificates", "version": "2025-02-25", "declared_directly": false},
+                {"full_name": "openssl@3", "version": "3.4.1", "declared_directly": true}
+            ]}"#,
+        );
+        let openssl = receipt(
+            r#"{"runtime_dependencies": [
+                {"full_name": "ca-certificates", "version": "2025-02-25", "declared_directly": true}
+            ]}"#,
+        );
+
+        assert_eq!(
+            infer_dependencies(&[
+                ("ca-certificates", None),
+                ("curl", Some(&curl)),
+                ("openssl@3", Some(&openssl)),
+            ]),
+            [
+                (Some("curl"), "openssl@3"),
+                (Some("openssl@3"), "ca-certificates"),
+                (None, "curl"),
+            ],
+        );
+    }
+
+    #[test]
+    fn dependencies_installed_on_request_are_not_roots() {
+        let curl = receipt(
+            r#"{"runtime_dependencies": [
+                {"full_name": "openssl@3", "version": "3.4.1", "declared_directly": true}
+            ]}"#,
+        );
+        let openssl = receipt(r#"{"installed_on_request": true}"#);
+
+        assert_eq!(
+            infer_dependencies(&[("curl", Some(&curl)), ("openssl@3", Some(&openssl))]),
+            [(Some("curl"), "openssl@3"), (None, "curl")],
+        );
+    }
+
+    #[test]
+    fn ignores_dependencies_that_are_not_installed() {
+        let curl = receipt(
+            r#"{"runtime_dependencies": [
+                {"full_name": "openssl@3", "version": "3.4.1", "declared_directly": true}
+            ]}"#,
+        );
+
+        assert_eq!(
+            infer_dependencies(&[("curl", Some(&curl))]),
+            [(None, "curl")],
+        );
+    }
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("3.4.1", "3.4.1_1"), Ordering::Less);
+        assert_eq!(
+            compare_versions("2025-02-25", "2024-12-31"),
+            Ordering::Greater
+        );
+        assert_eq!(compare_versions("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0b", "1.0a"), Ordering::Greater);
+    }
+}
@@ -0,0 +1,131 @@
+//! Homebrew's `INSTALL_RECEIPT.json`, which records how a bottle was built
+
+use std::{fs, io, path::Path};
+
+use serde::Deserialize;
+
+use crate::formulae::Formula;
+
+const INSTALL_RECEIPT_FILE: &str = "INSTALL_RECEIPT.json";
+
+#[derive(Debug, Deserialize)]
+pub struct InstallReceipt {
+    pub homebrew_version: Option<String>,
+    #[serde(default)]
+    pub built_as_bottle: bool,
+    #[serde(default)]
+    pub installed_on_request: bool,
+    pub compiler: Option<String>,
+    pub runtime_dependencies: Option<Vec<ReceiptDependency>>,
+    pub source: Option<Source>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReceiptDependency {
+    pub full_name: String,
+    pub version: String,
+    #[serde(default)]
+    pub revision: i32,
+    /// Missing from older receipts, which listed every dependency
+    /// recursively rather than only the direct ones.
+    pub declared_directly: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Source {
+    pub tap: Option<String>,
+    pub versions: Option<SourceVersions>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SourceVersions {
+    pub stable: Option<String>,
+}
+
+impl InstallReceipt {
+    /// Reads the receipt from a bottle's directory, if it has one.
+    pub fn read(bottle_path: &Path) -> anyhow::Result<Option<Self>> {
+        let contents = match fs::read(bottle_path.join(INSTALL_RECEIPT_FILE)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        // The receipt is only informational, so don't fail the download over it
+        match serde_json::from_slice(&contents) {
+            Ok(receipt) => Ok(Some(receipt)),
+            Err(err) => {
+                eprintln!(
+                    "Warning: ignoring install receipt in {}: {err}",
+                    bottle_path.display(),
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Returns the names of the dependencies that a bottle declared directly,
+/// given the `(full_name, declared_directly)` of each dependency in its
+/// receipt. Older receipts don't say, so the formula's dependencies are used
+/// to pick the direct ones out of those.
+pub fn direct_dependencies<'a>(
+    formula_name: &str,
+    dependencies: impl IntoIterator<Item = (&'a str, Option<bool>)>,
+) -> Vec<&'a str> {
+    let dependencies = dependencies.into_iter().collect::<Vec<_>>();
+    if dependencies.iter().all(|(_, direct)| direct.is_some()) {
+        return dependencies
+            .into_iter()
+            .filter(|(_, direct)| *direct == Some(true))
+            .map(|(name, _)| name)
+            .collect();
+    }
+
+    // Without the formula, keeping every dependency is the safe choice
+    let Ok(formula) = Formula::get_exact(formula_name) else {
+        return dependencies.into_iter().map(|(name, _)| name).collect();
+    };
+    dependencies
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| formula.dependencies.iter().any(|d| d == name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstallReceipt, direct_dependencies};
+
+    #[test]
+    fn uses_declared_directly_when_known() {
+        let receipt = serde_json::from_str::<InstallReceipt>(
+            r#"{"runtime_dependencies": [
+                {"full_name": "ca-certificates", "version": "2025-02-25", "declared_directly": false},
+                {"full_name": "openssl@3", "version": "3.4.1", "declared_directly": true}
+            ]}"#,
+        )
+        .unwrap();
+        let dependencies = receipt.runtime_dependencies.as_deref().unwrap();
+
+        assert_eq!(
+            direct_dependencies(
+                "curl",
+                dependencies
+                    .iter()
+                    .map(|d| (d.full_name.as_str(), d.declared_directly)),
+            ),
+            ["openssl@3"],
+        );
+    }
+
+    #[test]
+    fn older_receipts_leave_declared_directly_unknown() {
+        let receipt = serde_json::from_str::<InstallReceipt>(
+            r#"{"runtime_dependencies": [{"full_name": "openssl@3", "version": "3.4.1"}]}"#,
+        )
+        .unwrap();
+
+        let dependency = &receipt.runtime_dependencies.unwrap()[0];
+        assert_eq!(dependency.declared_directly, None);
+    }
+}
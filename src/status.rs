@@ -58,7 +58,7 @@ impl Progress {
         }
     }
 
-    pub fn start(&self, mut name: String) -> io::Result<ProgressHandle> {
+    pub fn start(&self, mut name: String) -> io::Result<ProgressHandle<'_>> {
         let mut inner = self.inner.lock().unwrap();
 
         if name.len() > NAME_LENGTH {
@@ -103,7 +103,7 @@ impl ProgressHandle<'_> {
         Ok(())
     }
 
-    pub fn track<R: io::Read + ContentLength>(&self, read: R) -> Track<R> {
+    pub fn track<R: io::Read + ContentLength>(&self, read: R) -> Track<'_, R> {
         Track {
             handle: self,
             content_length: read.content_length(),
@@ -227,3 +227,34 @@ fn output_width() -> usize {
         .map_or(FALLBACK_WIDTH, |(Width(w), Height(_))| w as usize)
         .min(MAX_WIDTH)
 }
+
+/// Formats a number of bytes for display, e.g. `12.3 MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "kB", "MB", "GB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_size;
+
+    #[test]
+    fn format_size_uses_the_largest_unit() {
+        assert_eq!(format_size(999), "999 B");
+        assert_eq!(format_size(1_000), "1.0 kB");
+        assert_eq!(format_size(12_345_678), "12.3 MB");
+        assert_eq!(format_size(5_000_000_000_000), "5000.0 GB");
+    }
+}
@@ -1,5 +1,7 @@
 use std::{fmt, process::Command};
 
+use anyhow::Context;
+
 #[derive(Debug)]
 pub struct Target {
     arch: String,
@@ -9,20 +11,39 @@ pub struct Target {
 impl Target {
     pub fn current() -> anyhow::Result<Self> {
         let arch = command_output("uname", &["-m"])?;
-        let mut os = command_output("uname", &["-s"])?.to_lowercase();
-
-        if os == "darwin" {
-            let version = command_output("sw_vers", &["--productVersion"])?;
-            let major_version = version.split('.').next().unwrap();
-            match major_version {
-                "13" => os = "ventura".to_owned(),
-                "14" => os = "sonoma".to_owned(),
-                "15" => os = "sequoia".to_owned(),
-                "16" => os = "cheer".to_owned(),
-                _ => anyhow::bail!("Unsupported macOS version: {version}"),
-            }
+        let os = command_output("uname", &["-s"])?;
+        let macos_version = if os == "Darwin" {
+            Some(command_output("sw_vers", &["--productVersion"])?)
+        } else {
+            None
+        };
+
+        Target::from_uname(arch, &os, macos_version.as_deref())
+    }
+
+    /// Converts the output of `uname` into the names Homebrew uses.
+    fn from_uname(mut arch: String, os: &str, macos_version: Option<&str>) -> anyhow::Result<Self> {
+        // Homebrew uses Apple's name for 64-bit ARM on every platform
+        if arch == "aarch64" {
+            arch = "arm64".to_owned();
         }
 
+        let os = match os.to_lowercase().as_str() {
+            "darwin" => {
+                let version = macos_version.context("Unknown macOS version")?;
+                let major_version = version.split('.').next().unwrap();
+                match major_version {
+                    "13" => "ventura".to_owned(),
+                    "14" => "sonoma".to_owned(),
+                    "15" => "sequoia".to_owned(),
+                    "16" => "cheer".to_owned(),
+                    _ => anyhow::bail!("Unsupported macOS version: {version}"),
+                }
+            }
+            "linux" => "linux".to_owned(),
+            os => anyhow::bail!("Unsupported operating system: {os}"),
+        };
+
         Ok(Target { arch, os })
     }
 
@@ -37,7 +58,12 @@ impl Target {
 
 impl fmt::Display for Target {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}_{}", self.arch, self.os)
+        // Intel macOS bottles are keyed by the OS name alone
+        if self.arch == "x86_64" && self.os != "linux" {
+            write!(f, "{}", self.os)
+        } else {
+            write!(f, "{}_{}", self.arch, self.os)
+        }
     }
 }
 
@@ -51,3 +77,28 @@ fn command_output(command: &str, args: &[&str]) -> anyhow::Result<String> {
 
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Target;
+
+    fn target(arch: &str, os: &str, macos_version: Option<&str>) -> String {
+        Target::from_uname(arch.to_owned(), os, macos_version)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn uses_homebrew_names() {
+        assert_eq!(target("aarch64", "Linux", None), "arm64_linux");
+        assert_eq!(target("x86_64", "Linux", None), "x86_64_linux");
+        assert_eq!(target("arm64", "Darwin", Some("15.3.1")), "arm64_sequoia");
+        assert_eq!(target("x86_64", "Darwin", Some("14.0")), "sonoma");
+    }
+
+    #[test]
+    fn rejects_unsupported_platforms() {
+        assert!(Target::from_uname("x86_64".to_owned(), "FreeBSD", None).is_err());
+        assert!(Target::from_uname("arm64".to_owned(), "Darwin", Some("12.7")).is_err());
+    }
+}
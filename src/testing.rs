@@ -0,0 +1,46 @@
+//! Helpers shared by the unit tests
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::OnceLock,
+};
+
+/// The home dir that the dirs use in unit tests, see `home`.
+pub static HOME: OnceLock<PathBuf> = OnceLock::new();
+
+/// Gives the dirs a home in the temp dir that is shared by the tests in this
+/// process, and must be called before they are used. Unlike `TempDir`, it is
+/// left behind, as statics are never dropped.
+pub fn home() -> &'static Path {
+    HOME.get_or_init(|| {
+        let path = env::temp_dir().join(format!("chug-test-{}-home", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    })
+}
+
+/// An empty directory in the system temp dir that is unique to this process
+/// and `name`, and is removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("chug-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
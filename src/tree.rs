@@ -2,13 +2,14 @@ use std::collections::{BTreeMap, BTreeSet};
 
 use ptree::{TreeBuilder, print_tree};
 
-use crate::db::models::{Dependency, DownloadedBottle};
+use crate::db::models::{Dependency, DownloadedBottle, PinnedFormula};
 
 pub fn list_bottles() -> anyhow::Result<()> {
-    let bottles = DownloadedBottle::get_all()?;
+    let bottles = DownloadedBottle::get_installed()?;
+    let pinned = pinned_names()?;
 
     for bottle in bottles {
-        println!("{} {}", bottle.name(), bottle.version());
+        println!("{}", describe(&bottle, &pinned));
     }
 
     Ok(())
@@ -17,6 +18,7 @@ pub fn list_bottles() -> anyhow::Result<()> {
 pub fn display_tree() -> anyhow::Result<()> {
     let bottles = DownloadedBottle::get_all()?;
     let dependencies = Dependency::get_all()?;
+    let pinned = pinned_names()?;
 
     let bottle_map = bottles
         .into_iter()
@@ -48,10 +50,10 @@ pub fn display_tree() -> anyhow::Result<()> {
     while let Some(children) = stack.last_mut() {
         if let Some(child) = children.next() {
             if processed.insert(child.id()) {
-                builder.begin_child(format!("{} {}", child.name(), child.version()));
+                builder.begin_child(describe(child, &pinned));
                 stack.push(get_dependencies(Some(child.id())).into_iter());
             } else {
-                builder.add_empty_child(format!("{} {} (*)", child.name(), child.version()));
+                builder.add_empty_child(format!("{} (*)", describe(child, &pinned)));
             }
         } else {
             stack.pop();
@@ -66,3 +68,19 @@ pub fn display_tree() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+fn pinned_names() -> anyhow::Result<BTreeSet<String>> {
+    let pinned = PinnedFormula::get_all()?
+        .iter()
+        .map(|p| p.name().to_owned())
+        .collect();
+    Ok(pinned)
+}
+
+fn describe(bottle: &DownloadedBottle, pinned: &BTreeSet<String>) -> String {
+    if pinned.contains(bottle.name()) {
+        format!("{} {} (pinned)", bottle.name(), bottle.version())
+    } else {
+        format!("{} {}", bottle.name(), bottle.version())
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    collections::BTreeSet,
+    fmt, fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    db::models::DownloadedBottle,
+    manifest::{self, FileKind, ManifestEntry},
+};
+
+#[derive(Debug)]
+enum Problem {
+    Missing(PathBuf),
+    Modified(PathBuf),
+    Extra(PathBuf),
+    PermissionsChanged {
+        path: PathBuf,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+/// Checks the named bottles, or all bottles if none are named, against their
+/// manifests.
+pub fn verify_bottles(names: &[String]) -> anyhow::Result<()> {
+    let bottles = select_bottles(names)?;
+
+    // The DB connection is shared, so load everything before hashing in parallel
+    let manifests = bottles
+        .iter()
+        .map(load_manifest)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let results = bottles
+        .par_iter()
+        .zip(&manifests)
+        .map(|(bottle, manifest)| verify_bottle(bottle.path(), manifest))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut failed = 0;
+    for (bottle, problems) in bottles.iter().zip(results) {
+        match problems {
+            None => println!(
+                "{} {}: No manifest recorded, skipping",
+                bottle.name(),
+                bottle.version(),
+            ),
+            Some(problems) if problems.is_empty() => {
+                println!("{} {}: OK", bottle.name(), bottle.version());
+            }
+            Some(problems) => {
+                failed += 1;
+                println!("{} {}:", bottle.name(), bottle.version());
+                for problem in problems {
+                    println!("  {problem}");
+                }
+            }
+        }
+    }
+
+    anyhow::ensure!(
+        failed == 0,
+        "Verification failed for {failed} of {} bottles",
+        bottles.len(),
+    );
+
+    Ok(())
+}
+
+/// Includes the previous versions that are kept for `chug rollback`, which
+/// can be named as well.
+fn select_bottles(names: &[String]) -> anyhow::Result<Vec<DownloadedBottle>> {
+    let bottles = DownloadedBottle::get_all()?;
+    if names.is_empty() {
+        return Ok(bottles);
+    }
+
+    DownloadedBottle::select_by_names(&bottles, names)
+}
+
+fn load_manifest(bottle: &DownloadedBottle) -> anyhow::Result<Vec<ManifestEntry>> {
+    bottle
+        .manifest()?
+        .iter()
+        .map(|file| {
+            Ok(ManifestEntry {
+                path: file.path().to_owned(),
+                kind: file.kind()?,
+                mode: file.mode(),
+                size: file.size(),
+                sha256: file.sha256().map(str::to_owned),
+                relocated: file.relocated(),
+            })
+        })
+        .collect()
+}
+
+/// Returns `None` if the bottle has no manifest, i.e. if it was downloaded by
+/// an older version of chug.
+fn verify_bottle(root: &Path, manifest: &[ManifestEntry]) -> anyhow::Result<Option<Vec<Problem>>> {
+    if manifest.is_empty() {
+        return Ok(None);
+    }
+
+    let mut problems = manifest
+        .par_iter()
+        .filter_map(|file| verify_file(root, file).transpose())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let expected = manifest
+        .iter()
+        .map(|f| f.path.as_path())
+        .collect::<BTreeSet<_>>();
+    if root.exists() {
+        for path in manifest::walk(root)? {
+            if !expected.contains(path.as_path()) {
+                problems.push(Problem::Extra(path));
+            }
+        }
+    }
+
+    Ok(Some(problems))
+}
+
+fn verify_file(root: &Path, file: &ManifestEntry) -> anyhow::Result<Option<Problem>> {
+    let path = root.join(&file.path);
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some(Problem::Missing(file.path.clone())));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let kind_matches = match file.kind {
+        FileKind::File | FileKind::HardLink => metadata.is_file(),
+        FileKind::Directory => metadata.is_dir(),
+        FileKind::Symlink => metadata.is_symlink(),
+    };
+    if !kind_matches {
+        return Ok(Some(Problem::Modified(file.path.clone())));
+    }
+
+    let (size, sha256) = manifest::hash_entry(&path, file.kind)?;
+    if size != file.size || sha256 != file.sha256 {
+        return Ok(Some(Problem::Modified(file.path.clone())));
+    }
+
+    // The permissions of symlinks can't be changed
+    if file.kind != FileKind::Symlink {
+        let expected = file.mode & 0o7777;
+        let actual = metadata.permissions().mode() & 0o7777;
+        if expected != actual {
+            return Ok(Some(Problem::PermissionsChanged {
+                path: file.path.clone(),
+                expected,
+                actual,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Missing(path) => write!(f, "missing: {}", path.display()),
+            Problem::Modified(path) => write!(f, "modified: {}", path.display()),
+            Problem::Extra(path) => write!(f, "extra: {}", path.display()),
+            Problem::PermissionsChanged {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "permissions changed: {} ({expected:o} -> {actual:o})",
+                path.display(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+    use super::verify_bottle;
+    use crate::{
+        manifest::{self, FileKind, ManifestEntry},
+        testing::TempDir,
+    };
+
+    const CONTENTS: &[u8] = b"#!/bin/sh\necho foo\n";
+
+    /// Creates a bottle with `bin/foo`, returning its root and manifest.
+    fn bottle(name: &str) -> (TempDir, Vec<ManifestEntry>) {
+        let root = TempDir::new(name);
+        let bin = root.path().join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("foo"), CONTENTS).unwrap();
+        fs::set_permissions(bin.join("foo"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let manifest = vec![
+            ManifestEntry {
+                path: "bin".into(),
+                kind: FileKind::Directory,
+                mode: fs::metadata(&bin).unwrap().permissions().mode(),
+                size: 0,
+                sha256: None,
+                relocated: false,
+            },
+            ManifestEntry {
+                path: "bin/foo".into(),
+                kind: FileKind::File,
+                mode: 0o100755,
+                size: CONTENTS.len() as u64,
+                sha256: Some(manifest::hash_bytes(CONTENTS)),
+                relocated: false,
+            },
+        ];
+
+        (root, manifest)
+    }
+
+    fn problems(root: &Path, manifest: &[ManifestEntry]) -> Vec<String> {
+        let problems = verify_bottle(root, manifest).unwrap().unwrap();
+
+        problems.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn reports_nothing_for_unchanged_bottle() {
+        let (root, manifest) = bottle("verify-unchanged");
+
+        assert!(problems(root.path(), &manifest).is_empty());
+    }
+
+    #[test]
+    fn skips_bottles_without_manifest() {
+        let (root, _) = bottle("verify-no-manifest");
+
+        assert!(verify_bottle(root.path(), &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        let (root, manifest) = bottle("verify-missing");
+        fs::remove_file(root.path().join("bin/foo")).unwrap();
+
+        assert_eq!(problems(root.path(), &manifest), ["missing: bin/foo"]);
+    }
+
+    #[test]
+    fn reports_modified_files() {
+        let (root, manifest) = bottle("verify-modified");
+        fs::write(root.path().join("bin/foo"), b"#!/bin/sh\necho bar\n").unwrap();
+
+        assert_eq!(problems(root.path(), &manifest), ["modified: bin/foo"]);
+    }
+
+    #[test]
+    fn reports_extra_files() {
+        let (root, manifest) = bottle("verify-extra");
+        fs::write(root.path().join("bin/bar"), CONTENTS).unwrap();
+
+        assert_eq!(problems(root.path(), &manifest), ["extra: bin/bar"]);
+    }
+
+    #[test]
+    fn reports_changed_permissions() {
+        let (root, manifest) = bottle("verify-permissions");
+        fs::set_permissions(
+            root.path().join("bin/foo"),
+            fs::Permissions::from_mode(0o777),
+        )
+        .unwrap();
+
+        assert_eq!(
+            problems(root.path(), &manifest),
+            ["permissions changed: bin/foo (755 -> 777)"],
+        );
+    }
+}
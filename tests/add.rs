@@ -1,5 +1,6 @@
-use std::{path::PathBuf, process::Command};
+use std::{fs, path::PathBuf, process::Command};
 
+mod bottle_server;
 mod output_dir;
 
 #[test]
@@ -33,3 +34,54 @@ fn test_add_python() {
     let ca_certs = output.data_dir().join("chug/etc/ca-certificates/cert.pem");
     assert!(ca_certs.exists());
 }
+
+#[test]
+fn test_add_keeps_untracked_bottle_dir() {
+    let output = output_dir::new();
+    let server = bottle_server::BottleServer::start();
+    bottle_server::write_formulae(
+        &output.cache_dir(),
+        &[server.add_bottle("foo", "1.0", &["foo"], &[])],
+    );
+
+    let bottle_dir = output.data_dir().join("chug/bottles/foo/1.0");
+    fs::create_dir_all(bottle_dir.join("bin")).unwrap();
+    fs::write(bottle_dir.join("bin/foo"), "#!/bin/sh\necho mine\n").unwrap();
+
+    let program = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/chug");
+    let result = Command::new(program).args(["add", "foo"]).output().unwrap();
+    assert!(!result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains("chug rebuild-db"), "{stderr}");
+    assert_eq!(
+        fs::read_to_string(bottle_dir.join("bin/foo")).unwrap(),
+        "#!/bin/sh\necho mine\n",
+    );
+}
+
+#[test]
+fn test_dry_run_json_is_valid_without_download_size() {
+    let output = output_dir::new();
+    let server = bottle_server::BottleServer::start();
+    // The archive isn't served from here, so its size can't be fetched
+    let formula = server
+        .add_bottle("foo", "1.0", &["foo"], &[])
+        .replace("/foo-1.0.tar.gz", "/missing.tar.gz");
+    bottle_server::write_formulae(&output.cache_dir(), &[formula]);
+
+    let program = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/chug");
+    let result = Command::new(program)
+        .args(["add", "foo", "--dry-run", "--json"])
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(
+        stderr.contains("could not get the download size"),
+        "{stderr}"
+    );
+
+    let plan = serde_json::from_slice::<serde_json::Value>(&result.stdout).unwrap();
+    assert_eq!(plan["add"][0]["name"], "foo");
+    assert!(plan["download_size"].is_null());
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use data_encoding::HEXLOWER;
+use flate2::{Compression, write::GzEncoder};
+use ring::digest;
+
+const TARGETS: &[&str] = &[
+    "arm64_linux",
+    "x86_64_linux",
+    "arm64_sequoia",
+    "arm64_sonoma",
+    "arm64_ventura",
+    "sequoia",
+    "sonoma",
+    "ventura",
+];
+
+/// Serves bottle archives by path, so that tests don't need the network.
+pub struct BottleServer {
+    url: String,
+    archives: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl BottleServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = BottleServer {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            archives: Arc::default(),
+        };
+
+        let archives = server.archives.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default();
+                let path = parts.next().unwrap_or_default();
+                let archives = archives.lock().unwrap();
+                match archives.get(path) {
+                    Some(archive) => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            archive.len(),
+                        )
+                        .unwrap();
+                        if method != "HEAD" {
+                            stream.write_all(archive).unwrap();
+                        }
+                    }
+                    None => write!(
+                        stream,
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .unwrap(),
+                }
+            }
+        });
+
+        server
+    }
+
+    /// Builds a bottle with an executable in `bin` for each of `bins`, which
+    /// prints the name and version of the bottle. Returns the formula JSON
+    /// that points at it.
+    pub fn add_bottle(
+        &self,
+        name: &str,
+        version: &str,
+        bins: &[&str],
+        dependencies: &[&str],
+    ) -> String {
+        let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for dir in [
+            format!("{name}/{version}/"),
+            format!("{name}/{version}/bin/"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar.append_data(&mut header, dir, &[][..]).unwrap();
+        }
+        let contents = format!("#!/bin/sh\necho {name} {version}\n");
+        for bin in bins {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o755);
+            header.set_size(contents.len() as u64);
+            tar.append_data(
+                &mut header,
+                format!("{name}/{version}/bin/{bin}"),
+                contents.as_bytes(),
+            )
+            .unwrap();
+        }
+        let archive = tar.into_inner().unwrap().finish().unwrap();
+
+        let sha256 = HEXLOWER.encode(digest::digest(&digest::SHA256, &archive).as_ref());
+        let path = format!("/{name}-{version}.tar.gz");
+        let url = format!("{}{path}", self.url);
+        self.archives.lock().unwrap().insert(path, archive);
+
+        let files = TARGETS
+            .iter()
+            .map(|target| format!(r#""{target}": {{"url": "{url}", "sha256": "{sha256}"}}"#))
+            .collect::<Vec<_>>()
+            .join(",");
+        let dependencies = serde_json::to_string(dependencies).unwrap();
+        format!(
+            r#"{{"name": "{name}", "aliases": [], "dependencies": {dependencies},
+                "versions": {{"stable": "{version}", "bottle": true}},
+                "bottle": {{"stable": {{"files": {{{files}}}}}}}}}"#
+        )
+    }
+}
+
+/// Replaces chug's cached list of formulae, so that it is not downloaded.
+pub fn write_formulae(cache_dir: &Path, formulae: &[String]) {
+    let cache_dir = cache_dir.join("chug");
+    fs::create_dir_all(&cache_dir).unwrap();
+    fs::write(
+        cache_dir.join("formula.json"),
+        format!("[{}]", formulae.join(",")),
+    )
+    .unwrap();
+}
@@ -0,0 +1,77 @@
+use std::{env, fs, path::PathBuf, process::Command};
+
+mod output_dir;
+
+/// Runs chug with the bin dir on `$PATH`, so that doctor doesn't report it.
+fn chug(output: &output_dir::OutputDir, args: &[&str]) -> (bool, String) {
+    let program = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/chug");
+    let path_var = env::var_os("PATH").unwrap_or_default();
+    let path_var = env::join_paths(
+        [output.bin_dir()]
+            .into_iter()
+            .chain(env::split_paths(&path_var)),
+    )
+    .unwrap();
+    let result = Command::new(program)
+        .args(args)
+        .env("PATH", path_var)
+        .output()
+        .unwrap();
+
+    (
+        result.status.success(),
+        String::from_utf8(result.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn test_doctor_keeps_untracked_bottle_dir() {
+    let output = output_dir::new();
+
+    let bottle_dir = output.data_dir().join("chug/bottles/foo/1.0");
+    fs::create_dir_all(bottle_dir.join("bin")).unwrap();
+    fs::write(bottle_dir.join("bin/foo"), "#!/bin/sh\n").unwrap();
+
+    let (success, stdout) = chug(&output, &["doctor"]);
+    assert!(!success);
+    assert!(stdout.contains("Bottle directory is not in the DB"));
+    assert!(stdout.contains("chug rebuild-db"));
+
+    let (success, stdout) = chug(&output, &["doctor", "--fix"]);
+    assert!(!success);
+    assert!(stdout.contains("Cannot be fixed automatically"));
+    assert!(bottle_dir.join("bin/foo").exists());
+}
+
+#[test]
+fn test_doctor_downloads_missing_bottle_dir() {
+    let output = output_dir::new();
+
+    let (success, _) = chug(&output, &["add", "gh"]);
+    assert!(success);
+
+    let gh_dir = output.data_dir().join("chug/bottles/gh");
+    let bottle_dir = fs::read_dir(&gh_dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    fs::remove_dir_all(&bottle_dir).unwrap();
+
+    let (success, stdout) = chug(&output, &["doctor"]);
+    assert!(!success);
+    assert!(stdout.contains("Directory for gh"));
+
+    let (success, stdout) = chug(&output, &["doctor", "--fix"]);
+    assert!(success, "{stdout}");
+    assert!(bottle_dir.is_dir());
+
+    let program = output.bin_dir().join("gh");
+    let status = Command::new(program).arg("--version").status().unwrap();
+    assert!(status.success());
+
+    let (success, stdout) = chug(&output, &["doctor"]);
+    assert!(success, "{stdout}");
+    assert!(stdout.contains("No problems found"));
+}
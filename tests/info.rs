@@ -0,0 +1,100 @@
+use std::{path::PathBuf, process::Command};
+
+mod bottle_server;
+mod output_dir;
+
+fn chug(args: &[&str]) -> (bool, String) {
+    let program = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/chug");
+    let result = Command::new(program).args(args).output().unwrap();
+
+    (
+        result.status.success(),
+        String::from_utf8(result.stdout).unwrap(),
+    )
+}
+
+/// Adds `fields` to the formula JSON from `BottleServer::add_bottle`.
+fn with_fields(formula: String, fields: serde_json::Value) -> String {
+    let mut formula = serde_json::from_str::<serde_json::Value>(&formula).unwrap();
+    for (key, value) in fields.as_object().unwrap() {
+        formula[key] = value.clone();
+    }
+    formula.to_string()
+}
+
+/// Writes `foo`, which is keg-only, has caveats and depends on `bar`.
+fn write_formulae(output: &output_dir::OutputDir) {
+    let server = bottle_server::BottleServer::start();
+    let foo_formula = with_fields(
+        server.add_bottle("foo", "1.0", &["foo"], &["bar"]),
+        serde_json::json!({
+            "desc": "Does foo things",
+            "keg_only": true,
+            "keg_only_reason": {"reason": ":versioned_formula", "explanation": ""},
+            "caveats": "Config is in $HOMEBREW_PREFIX/etc/foo\n\u{1b}[2Jdone\n",
+        }),
+    );
+    bottle_server::write_formulae(
+        &output.cache_dir(),
+        &[server.add_bottle("bar", "2.0", &["bar"], &[]), foo_formula],
+    );
+}
+
+#[test]
+fn test_info_text() {
+    let output = output_dir::new();
+    write_formulae(&output);
+
+    let (success, stdout) = chug(&["info", "foo"]);
+    assert!(success, "{stdout}");
+    assert!(stdout.starts_with("foo 1.0\nDoes foo things\n"), "{stdout}");
+    assert!(stdout.contains("  bar (not installed)"), "{stdout}");
+    assert!(
+        stdout.contains("Keg-only, because this is an alternate version of another formula."),
+        "{stdout}",
+    );
+    let config = output.data_dir().join("chug/etc/foo");
+    assert!(
+        stdout.contains(&format!(
+            "Caveats:\n  Config is in {}\n  [2Jdone\n",
+            config.display()
+        )),
+        "{stdout}",
+    );
+    assert!(!stdout.contains('\u{1b}'));
+    assert!(stdout.ends_with("Not installed\n"), "{stdout}");
+}
+
+#[test]
+fn test_info_json() {
+    let output = output_dir::new();
+    write_formulae(&output);
+
+    let (success, stdout) = chug(&["add", "foo"]);
+    assert!(success, "{stdout}");
+    let (success, stdout) = chug(&["pin", "foo"]);
+    assert!(success, "{stdout}");
+
+    let (success, stdout) = chug(&["info", "foo", "--json"]);
+    assert!(success, "{stdout}");
+    let info = serde_json::from_str::<serde_json::Value>(&stdout).unwrap();
+    assert_eq!(info["name"], "foo");
+    assert_eq!(info["version"], "1.0");
+    assert_eq!(info["dependencies"][0]["name"], "bar");
+    assert_eq!(info["dependencies"][0]["installed_version"], "2.0");
+    assert_eq!(
+        info["keg_only_reason"],
+        "this is an alternate version of another formula",
+    );
+    let config = output.data_dir().join("chug/etc/foo");
+    assert_eq!(
+        info["caveats"],
+        format!("Config is in {}\n\u{1b}[2Jdone\n", config.display()),
+    );
+    assert_eq!(info["installed"]["version"], "1.0");
+    assert_eq!(
+        PathBuf::from(info["installed"]["path"].as_str().unwrap()),
+        output.data_dir().join("chug/bottles/foo/1.0"),
+    );
+    assert_eq!(info["installed"]["pinned"], true);
+}
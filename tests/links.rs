@@ -0,0 +1,160 @@
+use std::{fs, path::PathBuf, process::Command};
+
+mod bottle_server;
+mod output_dir;
+
+fn chug(args: &[&str]) -> (bool, String) {
+    let program = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/chug");
+    let result = Command::new(program).args(args).output().unwrap();
+
+    (
+        result.status.success(),
+        String::from_utf8(result.stdout).unwrap(),
+    )
+}
+
+/// Adds `alpha` and `beta`, which both provide `bin/tool`.
+fn add_conflicting_bottles(output: &output_dir::OutputDir) {
+    let server = bottle_server::BottleServer::start();
+    bottle_server::write_formulae(
+        &output.cache_dir(),
+        &[
+            server.add_bottle("alpha", "1.0", &["alpha", "tool"], &[]),
+            server.add_bottle("beta", "1.0", &["beta", "tool"], &[]),
+        ],
+    );
+
+    let (success, stdout) = chug(&["add", "alpha", "beta"]);
+    assert!(success, "{stdout}");
+    assert!(stdout.contains(
+        "tool is provided by alpha and beta, linking alpha. Run `chug prefer tool <formula>` to choose"
+    ));
+}
+
+fn run_tool(output: &output_dir::OutputDir) -> String {
+    let result = Command::new(output.bin_dir().join("tool"))
+        .output()
+        .unwrap();
+    String::from_utf8(result.stdout).unwrap()
+}
+
+#[test]
+fn test_conflict_links_first_by_name() {
+    let output = output_dir::new();
+    add_conflicting_bottles(&output);
+
+    assert_eq!(run_tool(&output), "alpha 1.0\n");
+    assert!(output.bin_dir().join("alpha").exists());
+    assert!(output.bin_dir().join("beta").exists());
+
+    // Once the conflict is gone, the loser gets the link
+    let (success, stdout) = chug(&["remove", "alpha"]);
+    assert!(success, "{stdout}");
+    assert_eq!(run_tool(&output), "beta 1.0\n");
+}
+
+#[test]
+fn test_prefer_switches_link() {
+    let output = output_dir::new();
+    add_conflicting_bottles(&output);
+
+    let (success, stdout) = chug(&["prefer", "tool", "beta"]);
+    assert!(success, "{stdout}");
+    assert!(stdout.contains("linking beta as preferred"));
+    assert_eq!(run_tool(&output), "beta 1.0\n");
+
+    // Without a preference, the bottle that is already linked keeps the link
+    let (success, stdout) = chug(&["prefer", "tool"]);
+    assert!(success, "{stdout}");
+    assert_eq!(run_tool(&output), "beta 1.0\n");
+
+    let (success, stdout) = chug(&["prefer", "tool", "alpha"]);
+    assert!(success, "{stdout}");
+    assert_eq!(run_tool(&output), "alpha 1.0\n");
+}
+
+#[test]
+fn test_relink_forgets_unassigned_links() {
+    let output = output_dir::new();
+    add_conflicting_bottles(&output);
+
+    // A file that chug didn't create takes the place of the link, so neither
+    // bottle is assigned it any more
+    let tool = output.bin_dir().join("tool");
+    fs::remove_file(&tool).unwrap();
+    fs::write(&tool, "#!/bin/sh\necho mine\n").unwrap();
+
+    let (success, stdout) = chug(&["prefer", "tool", "beta"]);
+    assert!(success, "{stdout}");
+    assert!(stdout.contains("Skipping"));
+    assert_eq!(fs::read_to_string(&tool).unwrap(), "#!/bin/sh\necho mine\n");
+
+    let (_, stdout) = chug(&["doctor"]);
+    assert!(!stdout.contains("is recorded as linked by"), "{stdout}");
+}
+
+#[test]
+fn test_unlink_passes_link_to_loser() {
+    let output = output_dir::new();
+    add_conflicting_bottles(&output);
+
+    let (success, stdout) = chug(&["unlink", "alpha"]);
+    assert!(success, "{stdout}");
+    assert!(!output.bin_dir().join("alpha").exists());
+    assert_eq!(run_tool(&output), "beta 1.0\n");
+
+    // beta is linked now, so it keeps the link
+    let (success, stdout) = chug(&["link", "alpha"]);
+    assert!(success, "{stdout}");
+    assert!(output.bin_dir().join("alpha").exists());
+    assert_eq!(run_tool(&output), "beta 1.0\n");
+}
+
+#[test]
+fn test_prefer_requires_formula_to_provide_file() {
+    let output = output_dir::new();
+    add_conflicting_bottles(&output);
+
+    let (success, _) = chug(&["prefer", "tool", "gamma"]);
+    assert!(!success);
+    let (success, _) = chug(&["prefer", "alpha", "beta"]);
+    assert!(!success);
+    assert_eq!(run_tool(&output), "alpha 1.0\n");
+}
+
+#[test]
+fn test_overwrite_skips_directories() {
+    let output = output_dir::new();
+    let server = bottle_server::BottleServer::start();
+    bottle_server::write_formulae(
+        &output.cache_dir(),
+        &[server.add_bottle("alpha", "1.0", &["alpha", "tool"], &[])],
+    );
+    let tool = output.bin_dir().join("tool");
+    fs::create_dir_all(tool.join("data")).unwrap();
+
+    let (success, stdout) = chug(&["add", "--overwrite", "alpha"]);
+    assert!(success, "{stdout}");
+    assert!(stdout.contains("which is a directory"), "{stdout}");
+    assert!(tool.join("data").is_dir());
+    assert!(output.bin_dir().join("alpha").exists());
+}
+
+#[test]
+fn test_rollback_undoes_link_changes() {
+    let output = output_dir::new();
+    add_conflicting_bottles(&output);
+
+    let (success, stdout) = chug(&["unlink", "alpha"]);
+    assert!(success, "{stdout}");
+    let (success, stdout) = chug(&["rollback"]);
+    assert!(success, "{stdout}");
+    assert!(output.bin_dir().join("alpha").exists());
+    assert_eq!(run_tool(&output), "alpha 1.0\n");
+
+    let (success, stdout) = chug(&["prefer", "tool", "beta"]);
+    assert!(success, "{stdout}");
+    let (success, stdout) = chug(&["rollback"]);
+    assert!(success, "{stdout}");
+    assert_eq!(run_tool(&output), "alpha 1.0\n");
+}
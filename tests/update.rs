@@ -0,0 +1,93 @@
+use std::{fs, path::PathBuf, process::Command};
+
+mod bottle_server;
+mod output_dir;
+
+#[test]
+fn test_update_transfers_links() {
+    let output = output_dir::new();
+    let server = bottle_server::BottleServer::start();
+
+    let program = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/chug");
+    bottle_server::write_formulae(
+        &output.cache_dir(),
+        &[server.add_bottle("foo", "1.0", &["foo"], &[])],
+    );
+    let status = Command::new(&program)
+        .args(["add", "foo"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    bottle_server::write_formulae(
+        &output.cache_dir(),
+        &[server.add_bottle("foo", "2.0", &["foo"], &[])],
+    );
+    let status = Command::new(&program).arg("update").status().unwrap();
+    assert!(status.success());
+
+    let link = output.bin_dir().join("foo");
+    let new_path = output.data_dir().join("chug/bottles/foo/2.0");
+    assert_eq!(fs::read_link(&link).unwrap(), new_path.join("bin/foo"));
+    let result = Command::new(&link).output().unwrap();
+    assert_eq!(result.stdout, b"foo 2.0\n");
+
+    let status = Command::new(&program)
+        .args(["remove", "foo"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read_dir(output.bin_dir()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_update_fails_on_dependency_kept_by_pin() {
+    let output = output_dir::new();
+    let server = bottle_server::BottleServer::start();
+    let formulae = |version| {
+        [
+            server.add_bottle("curl", version, &["curl"], &["openssl"]),
+            server.add_bottle("openssl", version, &["openssl"], &[]),
+            server.add_bottle("python", version, &["python"], &["openssl"]),
+        ]
+    };
+
+    let program = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/debug/chug");
+    bottle_server::write_formulae(&output.cache_dir(), &formulae("1.0"));
+    let status = Command::new(&program)
+        .args(["add", "curl", "python"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new(&program)
+        .args(["pin", "python"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // python keeps openssl at 1.0, but the new curl needs 2.0
+    bottle_server::write_formulae(&output.cache_dir(), &formulae("2.0"));
+    let result = Command::new(&program).arg("update").output().unwrap();
+    assert!(!result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "curl 2.0 depends on openssl 2.0, but openssl 1.0 is kept by the pin on python, \
+            which depends on it. Run `chug unpin python` to allow it to be updated"
+        ),
+        "{stderr}",
+    );
+
+    let status = Command::new(&program)
+        .args(["unpin", "python"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new(&program).arg("update").status().unwrap();
+    assert!(status.success());
+
+    let result = Command::new(output.bin_dir().join("openssl"))
+        .output()
+        .unwrap();
+    assert_eq!(result.stdout, b"openssl 2.0\n");
+}